@@ -0,0 +1,633 @@
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use reqwest::{Error, Method, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, sleep_until, Instant};
+use tracing::{info, trace, warn};
+
+use crate::credentials::Credentials;
+
+const USER_AGENT: &str = concat!("convert_wiki/", env!("CARGO_PKG_VERSION"));
+
+/// Longest we wait between two attempts of a request, unless the wiki asks
+/// for more with `Retry-After`
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    /// Sent as `maxlag`, so that the wiki can ask us to wait when its database
+    /// replicas are lagging. See https://www.mediawiki.org/wiki/Manual:Maxlag_parameter
+    pub maxlag: Option<u32>,
+    /// Maximum number of requests started per second, across all tasks
+    pub requests_per_second: Option<f64>,
+    /// How many times a request is retried after a transient error
+    pub max_retries: u32,
+    pub timeout: Duration,
+    /// Used to log in before the first request, and again when the session expires
+    pub credentials: Option<Credentials>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            maxlag: Some(5),
+            requests_per_second: None,
+            max_retries: 5,
+            timeout: Duration::from_secs(60),
+            credentials: None,
+        }
+    }
+}
+
+/// Why a request to the wiki failed
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request couldn't be sent, or the wiki answered with an HTTP error
+    Http { request: String, source: Error },
+    /// The API answered with an `error` object, e.g. for a bad parameter,
+    /// missing rights or a read-only wiki
+    Api {
+        request: String,
+        code: String,
+        info: String,
+    },
+    /// The response isn't what we expected for this request
+    UnexpectedResponse {
+        request: String,
+        source: serde_json::Error,
+    },
+    /// The wiki refused our credentials
+    Login { username: String, reason: String },
+    /// None of the usual locations of `api.php` answered
+    ApiNotFound { url: String },
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Http { request, source } => {
+                write!(f, "Request {} failed: {}", request, source)
+            }
+            ApiError::Api {
+                request,
+                code,
+                info,
+            } => write!(f, "API error '{}' for {}: {}", code, request, info),
+            ApiError::UnexpectedResponse { request, source } => {
+                write!(f, "Unexpected response to {}: {}", request, source)
+            }
+            ApiError::Login { username, reason } => {
+                write!(f, "Could not log in as {}: {}", username, reason)
+            }
+            ApiError::ApiNotFound { url } => write!(f, "Could not find the API of {}", url),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApiError::Http { source, .. } => Some(source),
+            ApiError::Api { .. } | ApiError::Login { .. } | ApiError::ApiNotFound { .. } => None,
+            ApiError::UnexpectedResponse { source, .. } => Some(source),
+        }
+    }
+}
+
+/// HTTP client shared by every request made to the wiki.
+///
+/// It retries transient errors with jittered exponential backoff, honours
+/// `Retry-After` and `maxlag` errors, and limits the request rate. With
+/// credentials, it logs in before the first query and again when the session
+/// expires. Clones share the same rate limit and session.
+#[derive(Clone, Debug)]
+pub struct ApiClient {
+    client: reqwest::Client,
+    options: ClientOptions,
+    /// When the next request may start
+    next_request: Arc<Mutex<Instant>>,
+    /// How many times we logged in, 0 if we didn't yet
+    session: Arc<Mutex<u64>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginTokenResult {
+    query: LoginTokenQuery,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginTokenQuery {
+    tokens: LoginTokens,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginTokens {
+    logintoken: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginResult {
+    login: LoginStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginStatus {
+    result: String,
+    reason: Option<String>,
+}
+
+/// What to do after an attempt of a request
+enum Attempt<T> {
+    Done(Result<T, Error>),
+    /// Retry, after the given delay if the wiki asked for one. If there are no
+    /// retries left, the result is returned as is.
+    Retry(Option<Duration>, Result<T, Error>),
+}
+
+impl ApiClient {
+    pub fn new(options: ClientOptions) -> Self {
+        let mut headers = HeaderMap::new();
+        if let Some(Credentials::OAuth { token }) = &options.credentials {
+            let mut authorization = HeaderValue::from_str(&format!("Bearer {}", token)).unwrap();
+            authorization.set_sensitive(true);
+            headers.insert(AUTHORIZATION, authorization);
+        }
+        let client = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(options.timeout)
+            .cookie_store(true)
+            .default_headers(headers)
+            .build()
+            .unwrap();
+        Self {
+            client,
+            options,
+            next_request: Arc::new(Mutex::new(Instant::now())),
+            session: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// Sends a GET request to the API and deserializes its JSON response.
+    ///
+    /// Warnings in the response are logged, and an `error` object is returned
+    /// as `ApiError::Api`.
+    pub async fn query<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        params: &HashMap<&str, String>,
+    ) -> Result<T, ApiError> {
+        if self.options.credentials.is_none() {
+            return self.send_query(Method::GET, url, params).await;
+        }
+
+        // Makes the API fail instead of answering as an anonymous user
+        let mut params = params.clone();
+        params.insert("assert", "user".to_string());
+        let session = self.log_in(url, None).await?;
+        match self.send_query(Method::GET, url, &params).await {
+            Err(ApiError::Api { code, .. })
+                if code == "assertuserfailed"
+                    && matches!(
+                        self.options.credentials,
+                        Some(Credentials::BotPassword { .. })
+                    ) =>
+            {
+                warn!("Session expired, logging in again");
+                self.log_in(url, Some(session)).await?;
+                self.send_query(Method::GET, url, &params).await
+            }
+            result => result,
+        }
+    }
+
+    /// Logs in with the bot password if we didn't yet, or if the given session
+    /// expired and no other task logged in again since. Returns the current session.
+    async fn log_in(&self, url: &str, expired_session: Option<u64>) -> Result<u64, ApiError> {
+        let Some(Credentials::BotPassword { username, password }) = &self.options.credentials
+        else {
+            return Ok(0);
+        };
+        let mut session = self.session.lock().await;
+        if *session != expired_session.unwrap_or(0) {
+            return Ok(*session);
+        }
+
+        let mut params: HashMap<&str, String> = HashMap::new();
+        params.insert("action", "query".to_string());
+        params.insert("format", "json".to_string());
+        params.insert("meta", "tokens".to_string());
+        params.insert("type", "login".to_string());
+        let token: LoginTokenResult = self.send_query(Method::GET, url, &params).await?;
+
+        let mut params: HashMap<&str, String> = HashMap::new();
+        params.insert("action", "login".to_string());
+        params.insert("format", "json".to_string());
+        params.insert("lgname", username.clone());
+        params.insert("lgpassword", password.clone());
+        params.insert("lgtoken", token.query.tokens.logintoken);
+        let result: LoginResult = self.send_query(Method::POST, url, &params).await?;
+        if result.login.result != "Success" {
+            return Err(ApiError::Login {
+                username: username.clone(),
+                reason: result.login.reason.unwrap_or(result.login.result),
+            });
+        }
+
+        info!("Logged in as {}", username);
+        *session += 1;
+        Ok(*session)
+    }
+
+    async fn send_query<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        url: &str,
+        params: &HashMap<&str, String>,
+    ) -> Result<T, ApiError> {
+        let request = get_request_description(params);
+        let value = self
+            .query_value(method, url, params)
+            .await
+            .map_err(|source| ApiError::Http {
+                request: request.clone(),
+                source,
+            })?;
+        check_api_result(&request, params, value)
+    }
+
+    async fn query_value(
+        &self,
+        method: Method,
+        url: &str,
+        params: &HashMap<&str, String>,
+    ) -> Result<Value, Error> {
+        let maxlag = self.options.maxlag.map(|maxlag| ("maxlag", maxlag));
+        self.send_with_retries(|| async {
+            let request = self
+                .client
+                .request(method.clone(), url)
+                .query(&maxlag.as_slice());
+            // Passwords don't belong in URLs, which end up in logs
+            let request = if method == Method::POST {
+                request.form(params)
+            } else {
+                request.query(params)
+            };
+            let response = request.send().await;
+            let response = match check_response(response) {
+                Ok(response) => response,
+                Err(attempt) => return attempt,
+            };
+
+            let retry_after = get_retry_after(&response);
+            let is_json = response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok())
+                .is_none_or(|content_type| content_type.contains("json"));
+            match response.json::<Value>().await {
+                Ok(value) if value["error"]["code"] == "maxlag" => {
+                    warn!("Wiki is lagged: {}", value["error"]["info"]);
+                    Attempt::Retry(retry_after, Ok(value))
+                }
+                Ok(value) => Attempt::Done(Ok(value)),
+                // Usually a response cut short, unless we got an HTML page
+                Err(error) if error.is_body() || (error.is_decode() && is_json) => {
+                    Attempt::Retry(None, Err(error))
+                }
+                Err(error) => Attempt::Done(Err(error)),
+            }
+        })
+        .await
+    }
+
+    /// Sends a single GET request to the API, without retrying or logging in, e.g.
+    /// to check whether a URL is the API of a wiki before using it.
+    pub async fn probe<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        params: &HashMap<&str, String>,
+    ) -> Result<T, ApiError> {
+        let request = get_request_description(params);
+        self.wait_for_turn().await;
+        let value = async {
+            self.client
+                .get(url)
+                .query(params)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<Value>()
+                .await
+        }
+        .await
+        .map_err(|source| ApiError::Http {
+            request: request.clone(),
+            source,
+        })?;
+        check_api_result(&request, params, value)
+    }
+
+    /// Downloads a file, such as an uploaded image, from the wiki.
+    pub async fn download(&self, url: &str) -> Result<Vec<u8>, ApiError> {
+        let result = self
+            .send_with_retries(|| async {
+                let response = match check_response(self.client.get(url).send().await) {
+                    Ok(response) => response,
+                    Err(attempt) => return attempt,
+                };
+                match response.bytes().await {
+                    Ok(bytes) => Attempt::Done(Ok(bytes.to_vec())),
+                    Err(error) => Attempt::Retry(None, Err(error)),
+                }
+            })
+            .await;
+        result.map_err(|source| ApiError::Http {
+            request: url.to_string(),
+            source,
+        })
+    }
+
+    async fn send_with_retries<T, F, Fut>(&self, send: F) -> Result<T, Error>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = Attempt<T>>,
+    {
+        let mut retries = 0;
+        loop {
+            self.wait_for_turn().await;
+            match send().await {
+                Attempt::Done(result) => return result,
+                Attempt::Retry(_, result) if retries >= self.options.max_retries => {
+                    warn!("Giving up after {} retries", retries);
+                    return result;
+                }
+                Attempt::Retry(retry_after, result) => {
+                    let delay = retry_after.unwrap_or_else(|| get_backoff(retries));
+                    if let Err(error) = result {
+                        warn!("Request failed: {}", error);
+                    }
+                    warn!("Retrying in {:.1}s", delay.as_secs_f64());
+                    sleep(delay).await;
+                    retries += 1;
+                }
+            }
+        }
+    }
+
+    /// Waits until the rate limit allows a new request.
+    async fn wait_for_turn(&self) {
+        let Some(requests_per_second) = self.options.requests_per_second else {
+            return;
+        };
+        let interval = Duration::from_secs_f64(1.0 / requests_per_second);
+
+        let mut next_request = self.next_request.lock().await;
+        let start = (*next_request).max(Instant::now());
+        *next_request = start + interval;
+        drop(next_request);
+
+        if start > Instant::now() {
+            trace!("Rate limited, waiting");
+        }
+        sleep_until(start).await;
+    }
+}
+
+/// Describes a request in errors and logs, e.g. `list=allpages&apnamespace=1`
+fn get_request_description(params: &HashMap<&str, String>) -> String {
+    let mut params = params
+        .iter()
+        .filter(|(key, _)| !matches!(**key, "action" | "format" | "lgpassword" | "lgtoken"))
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>();
+    params.sort();
+    params.join("&")
+}
+
+/// Logs the warnings of an API response, and turns its error, if any, into an
+/// `ApiError`. `params` are those of the request, which the continuation of
+/// MediaWiki before 1.26 builds on.
+fn check_api_result<T: DeserializeOwned>(
+    request: &str,
+    params: &HashMap<&str, String>,
+    mut value: Value,
+) -> Result<T, ApiError> {
+    if let Some(warnings) = value.get("warnings").and_then(Value::as_object) {
+        for (module, warning) in warnings {
+            // The text is in `warnings` with errorformat=plaintext, `*` otherwise
+            let text = warning
+                .get("warnings")
+                .or_else(|| warning.get("*"))
+                .and_then(Value::as_str)
+                .map_or_else(|| warning.to_string(), str::to_string);
+            warn!("API warning from '{}' for {}: {}", module, request, text);
+        }
+    }
+
+    if let Some(error) = value.get("error") {
+        let field = |key| {
+            error
+                .get(key)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        return Err(ApiError::Api {
+            request: request.to_string(),
+            code: field("code"),
+            info: field("info"),
+        });
+    }
+
+    if let Some(query_continue) = value.get("query-continue") {
+        value["continue"] = get_continue_from_query_continue(query_continue, params);
+    }
+    serde_json::from_value(value).map_err(|source| ApiError::UnexpectedResponse {
+        request: request.to_string(),
+        source,
+    })
+}
+
+/// Turns the `query-continue` of MediaWiki before 1.26, which has the parameters
+/// to send back by module, into the flat `continue` of later versions.
+///
+/// The generator, whose parameters start with a "g", only moves on once the
+/// other modules are done with its current pages: until then, the generator
+/// parameters of the request are sent again as they were.
+fn get_continue_from_query_continue(
+    query_continue: &Value,
+    params: &HashMap<&str, String>,
+) -> Value {
+    let mut continue_params = serde_json::Map::new();
+    let Some(modules) = query_continue.as_object() else {
+        return Value::Object(continue_params);
+    };
+    let has_several_modules = modules.len() > 1;
+
+    for module_params in modules.values() {
+        for (key, value) in module_params.as_object().into_iter().flatten() {
+            let value = if has_several_modules && key.starts_with('g') {
+                // Absent from the first request, which starts at the beginning
+                match params.get(key.as_str()) {
+                    Some(value) => value.clone(),
+                    None => continue,
+                }
+            } else {
+                match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                }
+            };
+            continue_params.insert(key.clone(), Value::String(value));
+        }
+    }
+    Value::Object(continue_params)
+}
+
+/// Sorts out responses and errors that are worth retrying.
+fn check_response<T>(response: Result<Response, Error>) -> Result<Response, Attempt<T>> {
+    match response {
+        Ok(response) if is_transient_status(response.status()) => {
+            let retry_after = get_retry_after(&response);
+            Err(Attempt::Retry(
+                retry_after,
+                Err(response.error_for_status().unwrap_err()),
+            ))
+        }
+        Ok(response) => response
+            .error_for_status()
+            .map_err(|error| Attempt::Done(Err(error))),
+        Err(error) if error.is_timeout() || error.is_connect() || error.is_request() => {
+            Err(Attempt::Retry(None, Err(error)))
+        }
+        Err(error) => Err(Attempt::Done(Err(error))),
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Reads the `Retry-After` header, in its delay-seconds form.
+fn get_retry_after(response: &Response) -> Option<Duration> {
+    let retry_after = response.headers().get(reqwest::header::RETRY_AFTER)?;
+    let seconds = retry_after.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// Exponential backoff from one second, with jitter so that parallel tasks
+/// don't retry in lockstep.
+fn get_backoff(retries: u32) -> Duration {
+    let backoff = Duration::from_secs(1 << retries.min(6)).min(MAX_BACKOFF);
+    backoff.mul_f64(0.5 + fastrand::f64() / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_backoff() {
+        for retries in 0..10 {
+            let backoff = get_backoff(retries);
+            let expected = Duration::from_secs(1 << retries.min(6)).min(MAX_BACKOFF);
+            assert!(backoff <= expected);
+            assert!(backoff >= expected / 2);
+        }
+    }
+
+    #[test]
+    fn test_check_api_result() {
+        let params = HashMap::from([
+            ("action", "query".to_string()),
+            ("format", "json".to_string()),
+            ("list", "allpages".to_string()),
+            ("apnamespace", "42".to_string()),
+        ]);
+        let request = get_request_description(&params);
+        assert_eq!(request, "apnamespace=42&list=allpages");
+
+        let value = serde_json::json!({
+            "error": {"code": "badvalue", "info": "Unrecognized value for parameter \"apnamespace\": 42."}
+        });
+        let error = check_api_result::<Value>(&request, &params, value).unwrap_err();
+        assert!(matches!(&error, ApiError::Api { code, .. } if code == "badvalue"));
+        assert_eq!(
+            error.to_string(),
+            "API error 'badvalue' for apnamespace=42&list=allpages: Unrecognized value for parameter \"apnamespace\": 42."
+        );
+
+        let value = serde_json::json!({
+            "warnings": {"main": {"*": "Unrecognized parameter: foo."}},
+            "query": {"allpages": []}
+        });
+        let result = check_api_result::<HashMap<String, Value>>(&request, &params, value).unwrap();
+        assert!(result.contains_key("query"));
+
+        let value = serde_json::json!({
+            "query-continue": {
+                "revisions": {"rvcontinue": 1234},
+                "allpages": {"gapcontinue": "Beta"}
+            },
+            "query": {}
+        });
+        let result = check_api_result::<HashMap<String, Value>>(&request, &params, value).unwrap();
+        assert_eq!(
+            result["continue"],
+            serde_json::json!({"rvcontinue": "1234"})
+        );
+
+        // Later batches of revisions keep the pages the generator is at
+        let mut next_params = params.clone();
+        next_params.insert("gapcontinue", "Alpha".to_string());
+        next_params.insert("rvcontinue", "1234".to_string());
+        let value = serde_json::json!({
+            "query-continue": {
+                "revisions": {"rvcontinue": 1240},
+                "allpages": {"gapcontinue": "Beta"}
+            },
+            "query": {}
+        });
+        let result =
+            check_api_result::<HashMap<String, Value>>(&request, &next_params, value).unwrap();
+        assert_eq!(
+            result["continue"],
+            serde_json::json!({"rvcontinue": "1240", "gapcontinue": "Alpha"})
+        );
+
+        let value = serde_json::json!({
+            "query-continue": {"allpages": {"gapcontinue": "Beta"}},
+            "query": {}
+        });
+        let result = check_api_result::<HashMap<String, Value>>(&request, &params, value).unwrap();
+        assert_eq!(
+            result["continue"],
+            serde_json::json!({"gapcontinue": "Beta"})
+        );
+
+        let value = serde_json::json!({"batchcomplete": ""});
+        let error = check_api_result::<HashMap<String, u32>>(&request, &params, value).unwrap_err();
+        assert!(matches!(error, ApiError::UnexpectedResponse { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let client = ApiClient::new(ClientOptions {
+            requests_per_second: Some(20.0),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        for _ in 0..5 {
+            client.wait_for_turn().await;
+        }
+        // The first request doesn't wait
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
@@ -2,9 +2,73 @@ use std::fs::File;
 use std::io::{copy, Write};
 use std::path::Path;
 use std::process::{Command, Stdio};
+use std::sync::OnceLock;
 
+use regex::Regex;
 use tracing::{info_span, trace};
 
+/// Whether pages of a content model are wikitext, which is converted to Markdown.
+/// Code and data, such as CSS, JavaScript, JSON or Lua, are stored as is.
+pub fn is_wikitext(model: Option<&str>) -> bool {
+    matches!(model, None | Some("wikitext"))
+}
+
+/// Writes a page to a file, converted to Markdown if it's wikitext, or as is otherwise.
+pub fn write_page(file_path: &Path, title: &str, content: &str, model: Option<&str>) {
+    if is_wikitext(model) {
+        convert_file(file_path, title, content);
+    } else {
+        trace!("Writing '{}' as is", file_path.to_string_lossy());
+        std::fs::write(file_path, content).unwrap();
+    }
+}
+
+/// Returns the title a wikitext page redirects to, with its section if any, e.g.
+/// "Foo#Bar" for "#REDIRECT [[Foo#Bar]]". Only the English magic word is
+/// recognized, which works on every wiki.
+pub fn get_redirect_target(content: &str) -> Option<&str> {
+    static REDIRECT: OnceLock<Regex> = OnceLock::new();
+    let redirect = REDIRECT.get_or_init(|| {
+        Regex::new(r"(?i)^\s*#REDIRECT\s*:?\s*\[\[\s*:?([^\[\]|]+?)\s*(?:\|[^\]]*)?\]\]").unwrap()
+    });
+    let target = redirect.captures(content)?.get(1)?.as_str();
+    Some(target).filter(|target| !target.starts_with('#'))
+}
+
+/// Returns the anchor GitLab gives a heading, e.g. "see-also" for "See also".
+pub fn get_anchor(heading: &str) -> String {
+    heading
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Writes a redirect page as a short Markdown file linking to its target, given as
+/// a path relative to the file.
+pub fn write_redirect_stub(file_path: &Path, title: &str, target: &str, link: &str) {
+    trace!("Writing redirect to '{}'", file_path.to_string_lossy());
+    std::fs::write(
+        file_path,
+        format!("# {}\n\nRedirect to [{}]({})\n", title, target, link),
+    )
+    .unwrap();
+}
+
+/// Returns the link of a redirect stub written by `write_redirect_stub`, or `None`
+/// if the content isn't one.
+pub fn get_redirect_stub_link(content: &str) -> Option<&str> {
+    static REDIRECT_STUB: OnceLock<Regex> = OnceLock::new();
+    let redirect_stub = REDIRECT_STUB
+        .get_or_init(|| Regex::new(r"^# [^\n]*\n\nRedirect to \[[^\]]*\]\(([^)]*)\)\n$").unwrap());
+    Some(redirect_stub.captures(content)?.get(1)?.as_str())
+}
+
 pub fn convert_file(file_path: &Path, title: &str, content: &str) {
     let _span = info_span!("convert_file", title = title).entered();
 
@@ -14,13 +78,30 @@ pub fn convert_file(file_path: &Path, title: &str, content: &str) {
     trace!("Writing title to file");
     write!(file, "# {}\n\n", title).unwrap();
 
-    // run command, redirecting stdin and stdout to file_path
+    run_pandoc("mediawiki", "markdown", content, &mut file);
+}
+
+/// Converts a page written by `convert_file` back into wikitext.
+pub fn convert_to_wikitext(title: &str, content: &str) -> String {
+    let _span = info_span!("convert_to_wikitext", title = title).entered();
+
+    // The title was added by `convert_file` and isn't part of the page
+    let header = format!("# {}\n\n", title);
+    let content = content.strip_prefix(&header).unwrap_or(content);
+
+    let mut wikitext = Vec::new();
+    run_pandoc("markdown", "mediawiki", content, &mut wikitext);
+    String::from_utf8_lossy(&wikitext).into_owned()
+}
+
+fn run_pandoc(from: &str, to: &str, input: &str, output: &mut impl Write) {
+    // run command, redirecting stdin and stdout to output
     trace!("Running pandoc command");
     let mut child_process = Command::new("pandoc")
         .arg("-f")
-        .arg("mediawiki")
+        .arg(from)
         .arg("-t")
-        .arg("markdown")
+        .arg(to)
         .arg("-")
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
@@ -28,11 +109,12 @@ pub fn convert_file(file_path: &Path, title: &str, content: &str) {
         .unwrap();
 
     let stdin = child_process.stdin.as_mut().unwrap();
-    stdin.write_all(content.as_bytes()).unwrap();
+    stdin.write_all(input.as_bytes()).unwrap();
     std::mem::drop(child_process.stdin.take());
 
-    trace!("Writing output to file");
-    copy(&mut child_process.stdout.unwrap(), &mut file).unwrap();
+    trace!("Writing output");
+    copy(&mut child_process.stdout.take().unwrap(), output).unwrap();
+    child_process.wait().unwrap();
 }
 
 #[cfg(test)]
@@ -45,4 +127,38 @@ mod tests {
         convert_file(file_path, "Article title", "The Text of the file");
         dbg!(std::fs::read_to_string(file_path).unwrap());
     }
+
+    #[test]
+    fn test_get_redirect_target() {
+        assert_eq!(get_redirect_target("#REDIRECT [[Foo]]"), Some("Foo"));
+        assert_eq!(
+            get_redirect_target("#redirect:[[ Help:Foo#See also|Foo ]]\n[[Category:Bar]]"),
+            Some("Help:Foo#See also")
+        );
+        assert_eq!(
+            get_redirect_target("#REDIRECT [[:Category:Foo]]"),
+            Some("Category:Foo")
+        );
+        assert_eq!(get_redirect_target("See #REDIRECT [[Foo]]"), None);
+        assert_eq!(get_redirect_target("#REDIRECT [[#Foo]]"), None);
+    }
+
+    #[test]
+    fn test_get_anchor() {
+        assert_eq!(get_anchor("See also"), "see-also");
+        assert_eq!(get_anchor(" Tips & tricks "), "tips--tricks");
+    }
+
+    #[test]
+    fn test_get_redirect_stub_link() {
+        let file_path = std::env::temp_dir().join("test_get_redirect_stub_link.md");
+        write_redirect_stub(&file_path, "Beta", "Alpha#See also", "Alpha.md#see-also");
+        let content = std::fs::read_to_string(&file_path).unwrap();
+        assert_eq!(get_redirect_stub_link(&content), Some("Alpha.md#see-also"));
+        assert_eq!(
+            get_redirect_stub_link("# Beta\n\nSee [Alpha](Alpha.md)\n"),
+            None
+        );
+        std::fs::remove_file(&file_path).unwrap();
+    }
 }
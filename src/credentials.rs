@@ -0,0 +1,115 @@
+use serde::Deserialize;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// Environment variables read when no credentials file is given
+const USERNAME_VAR: &str = "CONVERT_WIKI_USERNAME";
+const PASSWORD_VAR: &str = "CONVERT_WIKI_PASSWORD";
+const OAUTH_TOKEN_VAR: &str = "CONVERT_WIKI_OAUTH_TOKEN";
+
+/// How to log in to wikis that require it to read
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credentials {
+    /// A bot password from Special:BotPasswords, used with `action=login`. The
+    /// username has the form `User@BotName`
+    BotPassword { username: String, password: String },
+    /// An OAuth 2 access token, sent as a bearer token with every request
+    OAuth { token: String },
+}
+
+// Keeps secrets out of logs
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::BotPassword { username, .. } => f
+                .debug_struct("BotPassword")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Credentials::OAuth { .. } => f.debug_struct("OAuth").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Contents of a credentials file, e.g. `{"username": "User@Bot", "password": "..."}`
+/// or `{"oauth_token": "..."}`
+#[derive(Debug, Default, Deserialize)]
+struct CredentialsFile {
+    username: Option<String>,
+    password: Option<String>,
+    oauth_token: Option<String>,
+}
+
+/// Reads credentials from a JSON file if one is given, or else from the
+/// `CONVERT_WIKI_USERNAME` and `CONVERT_WIKI_PASSWORD` or `CONVERT_WIKI_OAUTH_TOKEN`
+/// environment variables. Returns `None` if there are none.
+pub fn load_credentials(filename: Option<&Path>) -> Result<Option<Credentials>, Error> {
+    let file = match filename {
+        Some(filename) => serde_json::from_reader(std::fs::File::open(filename)?)?,
+        None => CredentialsFile {
+            username: std::env::var(USERNAME_VAR).ok(),
+            password: std::env::var(PASSWORD_VAR).ok(),
+            oauth_token: std::env::var(OAUTH_TOKEN_VAR).ok(),
+        },
+    };
+    get_credentials(file)
+}
+
+fn get_credentials(file: CredentialsFile) -> Result<Option<Credentials>, Error> {
+    match file {
+        CredentialsFile {
+            oauth_token: Some(token),
+            ..
+        } => Ok(Some(Credentials::OAuth { token })),
+        CredentialsFile {
+            username: Some(username),
+            password: Some(password),
+            ..
+        } => Ok(Some(Credentials::BotPassword { username, password })),
+        CredentialsFile {
+            username: None,
+            password: None,
+            ..
+        } => Ok(None),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Both a username and a password are needed to log in",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_credentials() {
+        let file: CredentialsFile =
+            serde_json::from_str(r#"{"username": "Foo@Bot", "password": "secret"}"#).unwrap();
+        let credentials = get_credentials(file).unwrap().unwrap();
+        assert_eq!(
+            credentials,
+            Credentials::BotPassword {
+                username: "Foo@Bot".into(),
+                password: "secret".into()
+            }
+        );
+        assert!(!format!("{:?}", credentials).contains("secret"));
+
+        let file: CredentialsFile = serde_json::from_str(r#"{"oauth_token": "abc"}"#).unwrap();
+        let credentials = get_credentials(file).unwrap().unwrap();
+        assert_eq!(
+            credentials,
+            Credentials::OAuth {
+                token: "abc".into()
+            }
+        );
+
+        assert!(get_credentials(CredentialsFile::default())
+            .unwrap()
+            .is_none());
+
+        let file: CredentialsFile = serde_json::from_str(r#"{"username": "Foo@Bot"}"#).unwrap();
+        assert!(get_credentials(file).is_err());
+    }
+}
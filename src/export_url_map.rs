@@ -0,0 +1,345 @@
+use std::collections::BTreeMap;
+use std::path::{Component, Path};
+
+use git2::{ObjectType, Repository, TreeWalkMode, TreeWalkResult};
+use tracing::{info, info_span, trace};
+use urlencoding::{decode, encode};
+
+use crate::convert_file::get_redirect_stub_link;
+use crate::handle_git::{get_most_recent_commit, get_page_title, read_redirect_map};
+use crate::parse_xml_dump::SiteInfo;
+
+/// Name of the nginx `map` block written by `export_url_map`
+pub const NGINX_URL_MAP_FILE: &str = "url_map.conf";
+/// Name of the Apache `RewriteMap` text file written by `export_url_map`
+pub const APACHE_URL_MAP_FILE: &str = "url_map.txt";
+/// Name of the JSON file written by `export_url_map`
+pub const JSON_URL_MAP_FILE: &str = "url_map.json";
+
+/// Writes maps from the URLs of the pages of the wiki to their location in the
+/// repository once rendered at `base_url`, for nginx, Apache and as JSON.
+///
+/// Redirect pages kept as symbolic links, stubs or in the map of redirects are
+/// mapped to the location of their target.
+pub fn export_url_map(
+    repository: &Repository,
+    branch_name: &str,
+    site_info: &SiteInfo,
+    base_url: &str,
+    output_dir: &Path,
+) -> std::io::Result<()> {
+    let _span = info_span!("export_url_map", branch_name).entered();
+
+    let url_map = get_url_map(repository, branch_name, site_info, base_url).unwrap();
+    info!(
+        "Writing {} URLs to '{}'",
+        url_map.len(),
+        output_dir.display()
+    );
+    std::fs::create_dir_all(output_dir)?;
+
+    let mut nginx = String::from(
+        "# Include in the http block, and redirect in the server block with e.g.\n\
+         #     if ($wiki_url) { return 301 $wiki_url; }\n\
+         map $request_uri $wiki_url {\n",
+    );
+    for (url, location) in &url_map {
+        nginx.push_str(&format!(
+            "    {} {};\n",
+            quote_nginx_string(url),
+            quote_nginx_string(location)
+        ));
+    }
+    nginx.push_str("}\n");
+    std::fs::write(output_dir.join(NGINX_URL_MAP_FILE), nginx)?;
+
+    let mut apache = String::from(
+        "# Redirect with e.g.\n\
+         #     RewriteMap wiki \"txt:/path/to/url_map.txt\"\n\
+         #     RewriteCond %{THE_REQUEST} \"^\\S+ (\\S+)\"\n\
+         #     RewriteCond ${wiki:%1} (.+)\n\
+         #     RewriteRule ^ %1 [R=301,L,NE]\n",
+    );
+    for (url, location) in &url_map {
+        apache.push_str(&format!("{} {}\n", url, location));
+    }
+    std::fs::write(output_dir.join(APACHE_URL_MAP_FILE), apache)?;
+
+    let json = format!("{}\n", serde_json::to_string_pretty(&url_map).unwrap());
+    std::fs::write(output_dir.join(JSON_URL_MAP_FILE), json)
+}
+
+/// Maps the URLs of every page of a branch to their location at `base_url`.
+pub fn get_url_map(
+    repository: &Repository,
+    branch_name: &str,
+    site_info: &SiteInfo,
+    base_url: &str,
+) -> Result<BTreeMap<String, String>, git2::Error> {
+    let tree = get_most_recent_commit(repository, branch_name)?.tree()?;
+    let mut entries = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |directory, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            if let Some(name) = entry.name() {
+                let path = Path::new(directory).join(name);
+                entries.push((path, entry.id(), entry.filemode()));
+            }
+        }
+        TreeWalkResult::Ok
+    })?;
+
+    // The location of each page, which is the file of the target for redirects
+    let mut locations = Vec::new();
+    for (path, id, filemode) in entries {
+        let Some((title, _)) = get_page_title(&path, site_info) else {
+            trace!("Skipping '{}', which isn't a page", path.to_string_lossy());
+            continue;
+        };
+        let blob = repository.find_blob(id)?;
+        let content = String::from_utf8_lossy(blob.content());
+        let location = if filemode == 0o120000 {
+            resolve_link(&path, &content)
+        } else if let Some(link) = get_redirect_stub_link(&content) {
+            // Stub links are escaped, unlike the targets of symbolic links
+            let (link, anchor) = split_anchor(link);
+            let link: String = link
+                .split('/')
+                .map(|component| decode(component).map_or(component.into(), |c| c.into_owned()))
+                .collect::<Vec<_>>()
+                .join("/");
+            resolve_link(&path, &format!("{}{}", link, anchor))
+        } else {
+            path.to_string_lossy().into_owned()
+        };
+        locations.push((title, location));
+    }
+    for (path, target) in read_redirect_map(repository, branch_name)? {
+        if let Some((title, _)) = get_page_title(Path::new(&path), site_info) {
+            locations.push((title, target));
+        }
+    }
+
+    let patterns = get_url_patterns(site_info);
+    let base_url = base_url.trim_end_matches('/');
+    let mut url_map = BTreeMap::new();
+    for (title, location) in locations {
+        let (path, anchor) = split_anchor(&location);
+        let path = path.split('/').map(encode).collect::<Vec<_>>().join("/");
+        let location = format!("{}/{}{}", base_url, path, anchor);
+        for pattern in &patterns {
+            url_map.insert(
+                pattern.replace("$1", &encode_title(&title)),
+                location.clone(),
+            );
+        }
+    }
+    Ok(url_map)
+}
+
+/// Splits a link into its path and its anchor, which keeps its "#".
+fn split_anchor(link: &str) -> (&str, &str) {
+    link.split_at(link.find('#').unwrap_or(link.len()))
+}
+
+/// Returns the path from the root of the repository of a link relative to a file,
+/// with its anchor if any.
+fn resolve_link(file_path: &Path, link: &str) -> String {
+    let (link, anchor) = split_anchor(link);
+    let mut path = file_path.parent().unwrap_or(Path::new("")).to_path_buf();
+    for component in Path::new(link).components() {
+        match component {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::Normal(name) => path.push(name),
+            _ => {}
+        }
+    }
+    format!("{}{}", path.to_string_lossy(), anchor)
+}
+
+/// Returns the paths of the URLs of pages, with "$1" for the title: the article
+/// path, e.g. "/title/$1", and that of index.php, e.g. "/index.php?title=$1".
+fn get_url_patterns(site_info: &SiteInfo) -> Vec<String> {
+    let (Some(script), Some(article_path)) = (&site_info.script, &site_info.article_path) else {
+        return get_url_patterns_from_base(&site_info.base);
+    };
+    let mut patterns = vec![get_url_path(article_path).to_string()];
+    let script_pattern = format!("{}?title=$1", get_url_path(script));
+    if !patterns.contains(&script_pattern) {
+        patterns.push(script_pattern);
+    }
+    patterns
+}
+
+/// Like `get_url_patterns`, from the URL of the main page only, as in dumps. For
+/// wikis with short URLs, index.php is assumed to be at the root of the site.
+fn get_url_patterns_from_base(base: &str) -> Vec<String> {
+    let path = get_url_path(base);
+    if let Some((script, _)) = path.split_once("?title=") {
+        return vec![format!("{}?title=$1", script)];
+    }
+    let Some((directory, _)) = path.rsplit_once('/') else {
+        return vec!["/index.php?title=$1".to_string()];
+    };
+    let script = if directory.ends_with("index.php") {
+        directory
+    } else {
+        "/index.php"
+    };
+    vec![format!("{}/$1", directory), format!("{}?title=$1", script)]
+}
+
+/// Returns the path of a URL, with its query, or the path itself.
+fn get_url_path(url: &str) -> &str {
+    url.split_once("://").map_or(url, |(_, url)| {
+        url.find('/').map_or("", |start| &url[start..])
+    })
+}
+
+/// Quotes a string for nginx, which would otherwise end it at whitespace or ";".
+fn quote_nginx_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Escapes a title like MediaWiki does in URLs, e.g. "Help:Foo_%26_bar" for
+/// "Help:Foo & bar".
+fn encode_title(title: &str) -> String {
+    let mut encoded = String::with_capacity(title.len());
+    for byte in title.replace(' ', "_").bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~;@$!*(),/:".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::handle_git::tests::create_test_repo;
+    use crate::handle_git::{commit_redirect_map, create_commit_from_metadata};
+
+    #[test]
+    fn test_get_url_patterns() {
+        let site_info = SiteInfo {
+            base: "https://en.wikipedia.org/wiki/Main_Page".to_string(),
+            script: Some("/w/index.php".to_string()),
+            article_path: Some("/wiki/$1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            get_url_patterns(&site_info),
+            ["/wiki/$1", "/w/index.php?title=$1"]
+        );
+
+        let site_info = SiteInfo {
+            script: Some("/index.php".to_string()),
+            article_path: Some("/index.php?title=$1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(get_url_patterns(&site_info), ["/index.php?title=$1"]);
+    }
+
+    #[test]
+    fn test_get_url_patterns_from_base() {
+        assert_eq!(
+            get_url_patterns_from_base("https://wiki.archlinux.org/title/Main_page"),
+            ["/title/$1", "/index.php?title=$1"]
+        );
+        assert_eq!(
+            get_url_patterns_from_base("http://example.com/w/index.php/Main_Page"),
+            ["/w/index.php/$1", "/w/index.php?title=$1"]
+        );
+        assert_eq!(
+            get_url_patterns_from_base("http://example.com/index.php?title=Main_Page"),
+            ["/index.php?title=$1"]
+        );
+        assert_eq!(get_url_patterns_from_base(""), ["/index.php?title=$1"]);
+    }
+
+    #[test]
+    fn test_quote_nginx_string() {
+        assert_eq!(quote_nginx_string("/title/A;_b"), r#""/title/A;_b""#);
+        assert_eq!(
+            quote_nginx_string(r#"/title/"Quote"\"#),
+            r#""/title/\"Quote\"\\""#
+        );
+    }
+
+    #[test]
+    fn test_encode_title() {
+        assert_eq!(encode_title("Help:Foo & bar"), "Help:Foo_%26_bar");
+        assert_eq!(encode_title("C++ (language)"), "C%2B%2B_(language)");
+        assert_eq!(encode_title("Élan/Sub"), "%C3%89lan/Sub");
+        assert_eq!(encode_title("A \"b\";\tc"), "A_%22b%22;%09c");
+    }
+
+    #[test]
+    fn test_resolve_link() {
+        let file_path = Path::new("Main/Beta.md");
+        assert_eq!(
+            resolve_link(file_path, "Alpha.md#see-also"),
+            "Main/Alpha.md#see-also"
+        );
+        assert_eq!(resolve_link(file_path, "../Help/Alpha.md"), "Help/Alpha.md");
+    }
+
+    #[test]
+    fn test_get_url_map() {
+        let (mut repository, repository_path, committer) = create_test_repo("test_get_url_map");
+
+        std::fs::create_dir_all(repository_path.join("Main")).unwrap();
+        std::fs::write(repository_path.join("Main/Alpha.md"), "# Alpha\n").unwrap();
+        std::fs::write(
+            repository_path.join("Main/Beta.md"),
+            "# Beta\n\nRedirect to [Alpha](Alpha.md#see-also)\n",
+        )
+        .unwrap();
+        std::fs::write(repository_path.join("Main/Gamma.md"), "Alpha.md").unwrap();
+        create_commit_from_metadata(
+            &mut repository,
+            committer.clone(),
+            committer.clone(),
+            "master",
+            &[
+                Path::new("Main/Alpha.md"),
+                Path::new("Main/Beta.md"),
+                Path::new("Main/Gamma.md"),
+            ],
+            &[Path::new("Main/Gamma.md")],
+            "Create",
+        );
+        let redirect_map =
+            BTreeMap::from([("Main/Delta.md".to_string(), "Main/Alpha.md".to_string())]);
+        commit_redirect_map(&repository, &committer, "master", &redirect_map).unwrap();
+
+        let site_info = SiteInfo {
+            base: "https://wiki.archlinux.org/title/Main_page".to_string(),
+            ..Default::default()
+        };
+        let url_map =
+            get_url_map(&repository, "master", &site_info, "https://example.com/").unwrap();
+        assert_eq!(
+            url_map.get("/title/Alpha").map(String::as_str),
+            Some("https://example.com/Main/Alpha.md")
+        );
+        assert_eq!(
+            url_map.get("/index.php?title=Beta").map(String::as_str),
+            Some("https://example.com/Main/Alpha.md#see-also")
+        );
+        assert_eq!(
+            url_map.get("/title/Gamma").map(String::as_str),
+            Some("https://example.com/Main/Alpha.md")
+        );
+        assert_eq!(
+            url_map.get("/title/Delta").map(String::as_str),
+            Some("https://example.com/Main/Alpha.md")
+        );
+        assert_eq!(url_map.len(), 8);
+        std::fs::remove_dir_all(&repository_path).unwrap();
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+
+use git2::{Oid, Repository, Signature, Sort, Tree};
+use quick_xml::se::to_string_with_root;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::{info, info_span, trace, warn};
+
+use crate::convert_file::convert_to_wikitext;
+use crate::fetch_revisions::{get_text_sha1, Contributor};
+use crate::get_author_data::AuthorData;
+use crate::handle_git::{
+    get_commit_comment, get_file_histories, get_most_recent_commit, get_page_file_title,
+    get_slot_file_names, get_slot_role, REDIRECT_MAP_FILE,
+};
+use crate::parse_xml_dump::{ContentDump, ContributorDump, PageDump, RevisionDump, SiteInfo};
+
+const MEDIAWIKI_START: &str = r#"<mediawiki xmlns="http://www.mediawiki.org/xml/export-0.11/" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.mediawiki.org/xml/export-0.11/ http://www.mediawiki.org/xml/export-0.11.xsd" version="0.11" xml:lang="en">"#;
+
+/// Writes the history of a branch as an `export-0.11` XML dump, which can be
+/// imported into MediaWiki with `importDump.php`.
+///
+/// Each page file becomes a `<page>`, with one `<revision>` per commit that
+/// changed it or its slot files. Markdown pages are converted back to wikitext
+/// with pandoc, while pages stored as is, such as CSS or Lua, keep their content
+/// and get the content model of their extension. Slot files become `<content>`
+/// elements of the revisions of their page.
+pub fn export_xml_dump(
+    repository: &Repository,
+    branch_name: &str,
+    site_info: &SiteInfo,
+    author_data: &AuthorData,
+    output: &mut impl Write,
+) -> std::io::Result<()> {
+    let _span = info_span!("export_xml_dump", branch_name).entered();
+
+    writeln!(output, "{}", MEDIAWIKI_START)?;
+    if !site_info.sitename.is_empty() {
+        writeln!(
+            output,
+            "{}",
+            to_string_with_root("siteinfo", site_info).unwrap()
+        )?;
+    }
+
+    let histories = get_file_histories(repository, branch_name).unwrap();
+    let commit_order = get_commit_order(repository, branch_name).unwrap();
+
+    // Slot files can look like pages of their own, e.g. "File/Foo.png.mediainfo.json"
+    let mut pages = Vec::new();
+    let mut slot_file_paths = HashSet::new();
+    for path in histories.keys() {
+        if path == Path::new(REDIRECT_MAP_FILE) {
+            continue;
+        }
+        if let Some((title, ns, model)) = get_page_file_title(path, site_info) {
+            let slots = get_slot_file_names(repository, branch_name, path).unwrap();
+            slot_file_paths.extend(slots.iter().cloned());
+            pages.push((path, title, ns, model, slots));
+        }
+    }
+    pages.retain(|(path, ..)| !slot_file_paths.contains(*path));
+    for path in histories.keys() {
+        let is_exported = path == Path::new(REDIRECT_MAP_FILE)
+            || slot_file_paths.contains(path)
+            || pages.iter().any(|(page_path, ..)| page_path == &path);
+        if !is_exported {
+            warn!(
+                "Skipping '{}', which is neither a page nor a slot",
+                path.to_string_lossy()
+            );
+        }
+    }
+
+    let mut revid = 0;
+    for (index, (path, title, ns, model, slots)) in pages.into_iter().enumerate() {
+        let mut commits: Vec<Oid> = [path]
+            .into_iter()
+            .chain(&slots)
+            .flat_map(|path| histories[path].iter().copied())
+            .collect();
+        commits.sort_by_key(|oid| commit_order[oid]);
+        commits.dedup();
+        info!("Exporting {} revisions of page '{}'", commits.len(), title);
+
+        let page = PageDump {
+            title,
+            ns,
+            id: index as u64 + 1,
+            redirect: None,
+        };
+        // Revisions are written one at a time, so we only keep the start of the page
+        let page_xml = to_string_with_root("page", &page).unwrap();
+        writeln!(output, "{}", page_xml.strip_suffix("</page>").unwrap())?;
+
+        let mut parentid = None;
+        for oid in commits {
+            let commit = repository.find_commit(oid).unwrap();
+            let tree = commit.tree().unwrap();
+            let Some(content) = read_file(repository, &tree, path) else {
+                trace!("Skipping a change to the slots of a missing page");
+                continue;
+            };
+            let timestamp = OffsetDateTime::from_unix_timestamp(commit.author().when().seconds())
+                .unwrap()
+                .format(&Rfc3339)
+                .unwrap();
+
+            let text = match model {
+                None => convert_to_wikitext(&page.title, &content),
+                Some(_) => content,
+            };
+            let model = model.unwrap_or("wikitext");
+            let contents: Vec<_> = slots
+                .iter()
+                .filter_map(|slot_file_path| {
+                    let text = read_file(repository, &tree, slot_file_path)?;
+                    let (role, model) = get_slot_role(path, slot_file_path).unwrap();
+                    Some(ContentDump {
+                        role,
+                        model: Some(model.to_string()),
+                        format: Some(get_content_format(model).to_string()),
+                        text,
+                    })
+                })
+                .collect();
+
+            revid += 1;
+            let revision = RevisionDump {
+                id: revid,
+                parentid,
+                timestamp,
+                contributor: get_contributor(&commit.author(), author_data),
+                minor: None,
+                comment: get_commit_comment(commit.message().unwrap_or_default()).to_string(),
+                model: Some(model.to_string()),
+                format: Some(get_content_format(model).to_string()),
+                sha1: Some(get_revision_sha1(&text, &contents)),
+                text,
+                text_deleted: false,
+                contents,
+            };
+            writeln!(
+                output,
+                "{}",
+                to_string_with_root("revision", &revision).unwrap()
+            )?;
+            parentid = Some(revid);
+        }
+
+        writeln!(output, "</page>")?;
+    }
+
+    writeln!(output, "</mediawiki>")?;
+    Ok(())
+}
+
+/// Numbers the commits of a branch from the oldest, to order the commits of the
+/// different files of a page.
+fn get_commit_order(
+    repository: &Repository,
+    branch_name: &str,
+) -> Result<HashMap<Oid, usize>, git2::Error> {
+    let mut revwalk = repository.revwalk()?;
+    revwalk.push(get_most_recent_commit(repository, branch_name)?.id())?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    revwalk
+        .enumerate()
+        .map(|(index, oid)| Ok((oid?, index)))
+        .collect()
+}
+
+fn read_file(repository: &Repository, tree: &Tree<'_>, path: &Path) -> Option<String> {
+    let blob = tree
+        .get_path(path)
+        .ok()?
+        .to_object(repository)
+        .unwrap()
+        .peel_to_blob()
+        .unwrap();
+    Some(String::from_utf8_lossy(blob.content()).into_owned())
+}
+
+/// Returns the default serialization format of a content model.
+fn get_content_format(model: &str) -> &'static str {
+    match model {
+        "wikitext" => "text/x-wiki",
+        "css" => "text/css",
+        "javascript" => "text/javascript",
+        "json" => "application/json",
+        _ => "text/plain",
+    }
+}
+
+/// Computes the SHA-1 of a revision like MediaWiki does: the one of its text if
+/// it only has a main slot, or else a combination of those of its slots, by role.
+fn get_revision_sha1(text: &str, contents: &[ContentDump]) -> String {
+    let mut slots: Vec<(&str, &str)> = contents
+        .iter()
+        .map(|content| (content.role.as_str(), content.text.as_str()))
+        .collect();
+    slots.push(("main", text));
+    slots.sort();
+
+    slots
+        .into_iter()
+        .map(|(_, text)| get_text_sha1(text))
+        .reduce(|sha1, slot_sha1| get_text_sha1(&(sha1 + &slot_sha1)))
+        .unwrap()
+}
+
+/// Finds the wiki user a commit author was created from by `get_author_git_data`.
+fn get_contributor(author: &Signature<'_>, author_data: &AuthorData) -> ContributorDump {
+    let name = author.name().unwrap_or_default();
+    let email = author.email().unwrap_or_default();
+
+    if name == Contributor::Hidden.to_string() {
+        return ContributorDump {
+            deleted: Some("deleted".to_string()),
+            ..Default::default()
+        };
+    }
+    if name.parse::<IpAddr>().is_ok() {
+        return ContributorDump {
+            ip: Some(name.to_string()),
+            ..Default::default()
+        };
+    }
+
+    let username = author_data
+        .authors
+        .iter()
+        .find(|(_, author)| author.name == name && author.email == email)
+        .map_or(name, |(username, _)| username.as_str());
+    ContributorDump {
+        username: Some(username.to_string()),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_author_data::Author;
+    use crate::handle_git::create_commit_from_metadata;
+    use crate::handle_git::tests::create_test_repo;
+    use crate::parse_xml_dump::DumpReader;
+
+    #[test]
+    fn test_get_contributor() {
+        let mut author_data = AuthorData::default();
+        author_data.authors.insert(
+            "Erus Iluvatar".into(),
+            Author {
+                name: "Erus".into(),
+                email: "erus@example.com".into(),
+            },
+        );
+        let time = git2::Time::new(0, 0);
+
+        let author = Signature::new("Erus", "erus@example.com", &time).unwrap();
+        let contributor = get_contributor(&author, &author_data);
+        assert_eq!(contributor.username.as_deref(), Some("Erus Iluvatar"));
+
+        let author = Signature::new("Someone", "no-email@example.com", &time).unwrap();
+        let contributor = get_contributor(&author, &author_data);
+        assert_eq!(contributor.username.as_deref(), Some("Someone"));
+
+        let author = Signature::new("127.0.0.1", "no-email@example.com", &time).unwrap();
+        let contributor = get_contributor(&author, &author_data);
+        assert_eq!(contributor.ip.as_deref(), Some("127.0.0.1"));
+
+        let author = Signature::new("(username removed)", "no-email@example.com", &time).unwrap();
+        let contributor = get_contributor(&author, &author_data);
+        assert!(contributor.deleted.is_some());
+        assert!(contributor.username.is_none());
+    }
+
+    #[test]
+    fn test_export_xml_dump() {
+        let (mut repository, repository_path, committer) = create_test_repo("test_export_xml_dump");
+
+        let file_path = Path::new("Main/Hello_world.md");
+        std::fs::create_dir_all(repository_path.join("Main")).unwrap();
+        for (day, text) in [(1, "Hello"), (2, "Hello, world")] {
+            std::fs::write(
+                repository_path.join(file_path),
+                format!("# Hello world\n\n{text}\n"),
+            )
+            .unwrap();
+            let author = Signature::new(
+                "Foo",
+                "no-email@example.com",
+                &git2::Time::new(day * 86400, 0),
+            )
+            .unwrap();
+            create_commit_from_metadata(
+                &mut repository,
+                committer.clone(),
+                author,
+                "master",
+                &[file_path],
+                &[],
+                &format!("Edit {day}\n\nWiki-Revision-Id: {day}\nWiki-Page-Id: 1"),
+            );
+        }
+
+        // Pages stored as is and slots keep their content
+        let css_file_path = Path::new("MediaWiki/Common.css");
+        let slot_file_path = Path::new("Main/Hello_world.mediainfo.json");
+        let upload_file_path = Path::new("File/Foo.png");
+        std::fs::create_dir_all(repository_path.join("MediaWiki")).unwrap();
+        std::fs::create_dir_all(repository_path.join("File")).unwrap();
+        std::fs::write(repository_path.join(css_file_path), "body {}\n").unwrap();
+        std::fs::write(repository_path.join(slot_file_path), "{}").unwrap();
+        std::fs::write(repository_path.join(upload_file_path), "PNG").unwrap();
+        let author = Signature::new(
+            "Foo",
+            "no-email@example.com",
+            &git2::Time::new(3 * 86400, 0),
+        )
+        .unwrap();
+        create_commit_from_metadata(
+            &mut repository,
+            committer.clone(),
+            author,
+            "master",
+            &[css_file_path, slot_file_path, upload_file_path],
+            &[],
+            "Edit 3\n\nWiki-Revision-Id: 3\nWiki-Page-Id: 2",
+        );
+
+        let mut dump = Vec::new();
+        export_xml_dump(
+            &repository,
+            "master",
+            &SiteInfo::default(),
+            &AuthorData::default(),
+            &mut dump,
+        )
+        .unwrap();
+
+        let mut reader = DumpReader::new(dump.as_slice());
+        let page = reader.next_page().unwrap().unwrap();
+        assert_eq!(page.title, "Hello world");
+        assert_eq!(page.ns, 0);
+
+        let first = reader.next_revision().unwrap().unwrap();
+        assert_eq!(first.parentid, None);
+        assert_eq!(first.timestamp, "1970-01-02T00:00:00Z");
+        assert_eq!(first.comment, "Edit 1");
+        assert_eq!(first.contributor.username.as_deref(), Some("Foo"));
+        assert_eq!(first.text, "Hello\n");
+        assert_eq!(first.sha1, Some(get_text_sha1("Hello\n")));
+
+        let second = reader.next_revision().unwrap().unwrap();
+        assert_eq!(second.parentid, Some(first.id));
+        assert_eq!(second.text, "Hello, world\n");
+        assert!(second.contents.is_empty());
+
+        let third = reader.next_revision().unwrap().unwrap();
+        assert_eq!(third.parentid, Some(second.id));
+        assert_eq!(third.text, "Hello, world\n");
+        assert_eq!(third.contents.len(), 1);
+        assert_eq!(third.contents[0].role, "mediainfo");
+        assert_eq!(third.contents[0].model.as_deref(), Some("json"));
+        assert_eq!(third.contents[0].text, "{}");
+        assert_ne!(third.sha1, second.sha1);
+        assert!(reader.next_revision().unwrap().is_none());
+
+        let page = reader.next_page().unwrap().unwrap();
+        assert_eq!(page.title, "MediaWiki:Common.css");
+        let revision = reader.next_revision().unwrap().unwrap();
+        assert_eq!(revision.model.as_deref(), Some("css"));
+        assert_eq!(revision.format.as_deref(), Some("text/css"));
+        assert_eq!(revision.text, "body {}\n");
+        assert_eq!(revision.sha1, Some(get_text_sha1("body {}\n")));
+
+        assert!(reader.next_revision().unwrap().is_none());
+        assert!(reader.next_page().unwrap().is_none());
+        std::fs::remove_dir_all(&repository_path).unwrap();
+    }
+}
@@ -1,12 +1,11 @@
-use reqwest::Error;
 use serde::Deserialize;
-use serde_json::Value;
 use std::collections::HashMap;
 
-#[derive(Debug, Deserialize)]
-pub struct ApContinueToken {
-    pub apcontinue: String,
-}
+use crate::api_client::{ApiClient, ApiError};
+use crate::fetch_site_info::MediaWikiVersion;
+
+/// Parameters to send back to continue the query, e.g. `apcontinue`
+pub type ApContinueToken = HashMap<String, String>;
 
 #[derive(Debug, Deserialize)]
 pub struct ApApiResult {
@@ -26,13 +25,19 @@ pub struct Page {
     pub title: String,
 }
 
+/// Fetches the pages of a namespace, optionally only those whose title, without
+/// the namespace prefix, starts with `prefix`, and only those that aren't redirects.
+#[allow(clippy::too_many_arguments)]
 pub async fn fetch_all_pages(
-    client: &reqwest::Client,
+    client: &ApiClient,
     url: &str,
     limit: Option<u32>,
     continue_token: Option<ApContinueToken>,
     namespace: u32,
-) -> Result<ApApiResult, Error> {
+    prefix: Option<&str>,
+    skip_redirects: bool,
+    version: &MediaWikiVersion,
+) -> Result<ApApiResult, ApiError> {
     let limit = limit.unwrap_or(50);
     let mut params: HashMap<&str, String> = HashMap::new();
     params.insert("action", "query".to_string());
@@ -40,36 +45,35 @@ pub async fn fetch_all_pages(
     params.insert("list", "allpages".to_string());
     params.insert("aplimit", limit.to_string());
     params.insert("apnamespace", namespace.to_string());
-    if let Some(continue_token) = continue_token {
-        params.insert("apcontinue", continue_token.apcontinue);
+    if let Some(prefix) = prefix {
+        params.insert("apprefix", prefix.to_string());
+    }
+    if skip_redirects {
+        params.insert("apfilterredir", "nonredirects".to_string());
     }
+    version.insert_continue(&mut params, continue_token.as_ref());
 
-    let resp = client
-        .get(url)
-        .query(&params)
-        .send()
-        .await?
-        .json::<Value>()
-        .await?;
-    Ok(serde_json::from_value(resp).unwrap())
+    client.query(url, &params).await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::api_client::ClientOptions;
     use insta::assert_debug_snapshot;
 
     #[tokio::test]
     async fn test_fetch_all_pages() {
-        let client = reqwest::Client::new();
+        let client = ApiClient::new(ClientOptions::default());
+        let version = MediaWikiVersion::default();
         let url = "https://wiki.archlinux.org/api.php".to_string();
 
-        let resp = fetch_all_pages(&client, &url, Some(4), None, 0)
+        let resp = fetch_all_pages(&client, &url, Some(4), None, 0, None, false, &version)
             .await
             .unwrap();
         assert_debug_snapshot!(resp.query);
 
-        let resp = fetch_all_pages(&client, &url, Some(4), resp.cont, 0)
+        let resp = fetch_all_pages(&client, &url, Some(4), resp.cont, 0, None, false, &version)
             .await
             .unwrap();
         assert_debug_snapshot!(resp.query);
@@ -77,15 +81,16 @@ mod tests {
 
     #[tokio::test]
     async fn test_fetch_all_pages_talk_namespace() {
-        let client = reqwest::Client::new();
+        let client = ApiClient::new(ClientOptions::default());
+        let version = MediaWikiVersion::default();
         let url = "https://wiki.archlinux.org/api.php".to_string();
 
-        let resp = fetch_all_pages(&client, &url, Some(4), None, 1)
+        let resp = fetch_all_pages(&client, &url, Some(4), None, 1, None, false, &version)
             .await
             .unwrap();
         assert_debug_snapshot!(resp.query);
 
-        let resp = fetch_all_pages(&client, &url, Some(4), resp.cont, 1)
+        let resp = fetch_all_pages(&client, &url, Some(4), resp.cont, 1, None, false, &version)
             .await
             .unwrap();
         assert_debug_snapshot!(resp.query);
@@ -0,0 +1,76 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::api_client::{ApiClient, ApiError};
+use crate::fetch_site_info::MediaWikiVersion;
+
+/// Namespace of categories, which is the same on every wiki
+pub const CATEGORY_NAMESPACE: u32 = 14;
+
+/// Parameters to send back to continue the query, e.g. `cmcontinue`
+pub type CmContinueToken = HashMap<String, String>;
+
+#[derive(Debug, Deserialize)]
+pub struct CmApiResult {
+    #[serde(rename = "continue")]
+    pub cont: Option<CmContinueToken>,
+    pub query: CmQueryResult,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CmQueryResult {
+    pub categorymembers: Vec<CategoryMember>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CategoryMember {
+    pub pageid: u64,
+    pub ns: u32,
+    pub title: String,
+}
+
+/// Fetches the pages, files and subcategories of a category, given by its full
+/// title, e.g. "Category:Foo".
+pub async fn fetch_category_members(
+    client: &ApiClient,
+    url: &str,
+    category: &str,
+    limit: Option<u32>,
+    continue_token: Option<CmContinueToken>,
+    version: &MediaWikiVersion,
+) -> Result<CmApiResult, ApiError> {
+    let limit = limit.unwrap_or(50);
+    let mut params: HashMap<&str, String> = HashMap::new();
+    params.insert("action", "query".to_string());
+    params.insert("format", "json".to_string());
+    params.insert("list", "categorymembers".to_string());
+    params.insert("cmtitle", category.to_string());
+    params.insert("cmprop", "ids|title".to_string());
+    params.insert("cmlimit", limit.to_string());
+    version.insert_continue(&mut params, continue_token.as_ref());
+
+    client.query(url, &params).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_category_members() {
+        let resp = serde_json::json!({
+            "continue": {"cmcontinue": "page|464f4f|3", "continue": "-||"},
+            "query": {"categorymembers": [
+                {"pageid": 1, "ns": 0, "title": "Foo"},
+                {"pageid": 2, "ns": 14, "title": "Category:Bar"}
+            ]}
+        });
+        let resp: CmApiResult = serde_json::from_value(resp).unwrap();
+        assert_eq!(resp.cont.unwrap()["cmcontinue"], "page|464f4f|3");
+
+        let members = resp.query.categorymembers;
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].title, "Foo");
+        assert_eq!(members[1].ns, CATEGORY_NAMESPACE);
+    }
+}
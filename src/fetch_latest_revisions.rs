@@ -0,0 +1,135 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::api_client::{ApiClient, ApiError};
+use crate::fetch_revisions::{
+    get_parsed_revisions, ParsedRevision, Revision, RvPage, RvQueryResult,
+};
+use crate::fetch_site_info::MediaWikiVersion;
+
+#[derive(Debug, Deserialize)]
+pub struct LrApiResult {
+    /// Generator continuation has several keys, which must all be sent back
+    #[serde(rename = "continue")]
+    pub cont: Option<HashMap<String, String>>,
+    /// Missing if the namespace has no pages
+    pub query: Option<LrQueryResult>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LrQueryResult {
+    pub pages: HashMap<String, LrPage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LrPage {
+    pub pageid: u64,
+    pub title: String,
+    /// Empty if the content didn't fit in this response, in which case the page
+    /// comes back in a later one
+    #[serde(default)]
+    pub revisions: Vec<Revision>,
+}
+
+/// Fetches the latest revision of many pages of a namespace at once, with
+/// `generator=allpages`, optionally only those whose title starts with `prefix`,
+/// and only those that aren't redirects.
+#[allow(clippy::too_many_arguments)]
+pub async fn fetch_latest_revisions(
+    client: &ApiClient,
+    url: &str,
+    limit: Option<u32>,
+    continue_token: Option<HashMap<String, String>>,
+    namespace: u32,
+    prefix: Option<&str>,
+    skip_redirects: bool,
+    version: &MediaWikiVersion,
+) -> Result<LrApiResult, ApiError> {
+    let limit = limit.unwrap_or(50);
+    let mut params: HashMap<&str, String> = HashMap::new();
+    params.insert("action", "query".to_string());
+    params.insert("format", "json".to_string());
+    params.insert("generator", "allpages".to_string());
+    params.insert("gaplimit", limit.to_string());
+    params.insert("gapnamespace", namespace.to_string());
+    if let Some(prefix) = prefix {
+        params.insert("gapprefix", prefix.to_string());
+    }
+    if skip_redirects {
+        params.insert("gapfilterredir", "nonredirects".to_string());
+    }
+    params.insert("prop", "revisions".to_string());
+    params.insert(
+        "rvprop",
+        "ids|timestamp|flags|user|comment|sha1|content|contentmodel".to_string(),
+    );
+    if version.has_slots() {
+        params.insert("rvslots", "*".to_string());
+    }
+    version.insert_continue(&mut params, continue_token.as_ref());
+
+    client.query(url, &params).await
+}
+
+/// Returns the latest revision of each page that has one in the response,
+/// in title order.
+pub fn get_parsed_latest_revisions(query: LrQueryResult) -> Vec<ParsedRevision> {
+    let mut pages = query.pages.into_values().collect::<Vec<_>>();
+    pages.sort_by(|a, b| a.title.cmp(&b.title));
+
+    let mut parsed_revisions = Vec::new();
+    for page in pages {
+        let Some(revision) = page.revisions.into_iter().next() else {
+            continue;
+        };
+        let query = RvQueryResult {
+            pages: HashMap::from([(
+                page.pageid.to_string(),
+                RvPage {
+                    revisions: vec![revision],
+                },
+            )]),
+        };
+        parsed_revisions.extend(get_parsed_revisions(query, page.title));
+    }
+    parsed_revisions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch_revisions::Contributor;
+
+    #[test]
+    fn test_parsed_latest_revisions() {
+        let resp = serde_json::json!({
+            "continue": {"rvcontinue": "42|1000", "continue": "||"},
+            "query": {"pages": {
+                "2": {"pageid": 2, "ns": 0, "title": "Zebra", "revisions": [{
+                    "revid": 20, "parentid": 0, "user": "127.0.0.1", "anon": "",
+                    "timestamp": "2023-01-02T00:00:00Z", "comment": "Create",
+                    "slots": {"main": {"contentmodel": "wikitext", "*": "Stripes"}}
+                }]},
+                "1": {"pageid": 1, "ns": 0, "title": "Aardvark", "revisions": [{
+                    "revid": 12, "parentid": 11, "user": "Foo",
+                    "timestamp": "2023-01-01T00:00:00Z", "comment": "Expand",
+                    "slots": {"main": {"contentmodel": "wikitext", "*": "Ants"}}
+                }]},
+                "3": {"pageid": 3, "ns": 0, "title": "Moose"}
+            }}
+        });
+        let resp: LrApiResult = serde_json::from_value(resp).unwrap();
+        assert_eq!(resp.cont.as_ref().unwrap()["rvcontinue"], "42|1000");
+
+        let revisions = get_parsed_latest_revisions(resp.query.unwrap());
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].title, "Aardvark");
+        assert_eq!(revisions[0].pageid, 1);
+        assert_eq!(revisions[0].parentid, Some(11));
+        assert_eq!(revisions[0].user, Contributor::User("Foo".into()));
+        assert_eq!(revisions[0].content, "Ants");
+        assert_eq!(revisions[1].title, "Zebra");
+        assert_eq!(revisions[1].parentid, Some(0));
+        assert_eq!(revisions[1].user, Contributor::Ip("127.0.0.1".into()));
+    }
+}
@@ -0,0 +1,276 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, PrimitiveDateTime};
+
+use crate::api_client::{ApiClient, ApiError};
+use crate::fetch_recent_changes::{get_move_target, RcLegacyMove, RcLogParams};
+use crate::fetch_revisions::{Contributor, HIDDEN_COMMENT};
+use crate::fetch_site_info::MediaWikiVersion;
+
+/// Parameters to send back to continue the query, e.g. `lecontinue`
+pub type LeContinueToken = HashMap<String, String>;
+
+#[derive(Debug, Deserialize)]
+pub struct LeApiResult {
+    #[serde(rename = "continue")]
+    pub cont: Option<LeContinueToken>,
+    pub query: LeQueryResult,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LeQueryResult {
+    pub logevents: Vec<LogEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogEvent {
+    pub logid: u64,
+    pub ns: u32,
+    pub title: String,
+    /// e.g. "move", "delete" or "restore"
+    #[serde(default)]
+    pub action: String,
+    /// The id of the page the entry is about, e.g. the page that was moved
+    #[serde(default)]
+    pub logpage: u64,
+    /// Missing if the user was hidden by revision deletion
+    #[serde(default)]
+    pub user: String,
+    #[serde(default)]
+    pub userhidden: Option<String>,
+    pub timestamp: String,
+    /// Missing if the comment was hidden by revision deletion
+    #[serde(default)]
+    pub comment: String,
+    #[serde(default)]
+    pub commenthidden: Option<String>,
+    /// The parameters of the entry, e.g. the new title of moves
+    #[serde(default)]
+    pub params: Option<RcLogParams>,
+    /// The parameters of moves, before MediaWiki 1.25
+    #[serde(rename = "move", default)]
+    pub legacy_move: Option<RcLegacyMove>,
+}
+
+/// A page move, from the move log
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParsedMove {
+    pub logid: u64,
+    pub pageid: u64,
+    pub timestamp: PrimitiveDateTime,
+    pub user: Contributor,
+    pub comment: String,
+    pub old_namespace: u32,
+    pub old_title: String,
+    pub new_namespace: u32,
+    pub new_title: String,
+}
+
+/// A page deletion or undeletion, from the deletion log
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParsedDeletion {
+    pub logid: u64,
+    /// 0 for deletions logged before MediaWiki recorded it
+    pub pageid: u64,
+    pub timestamp: PrimitiveDateTime,
+    pub user: Contributor,
+    pub comment: String,
+    pub namespace: u32,
+    pub title: String,
+    /// Whether the page was undeleted rather than deleted
+    pub restored: bool,
+}
+
+/// A change to pages from the logs, which has to be applied in order with the others
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParsedLogEvent {
+    Move(ParsedMove),
+    Deletion(ParsedDeletion),
+}
+
+impl ParsedLogEvent {
+    /// When the change happened, with the id of the log entry to order changes
+    /// logged at the same time, e.g. the deletion of a redirect a page moves over
+    pub fn get_order(&self) -> (PrimitiveDateTime, u64) {
+        match self {
+            ParsedLogEvent::Move(page_move) => (page_move.timestamp, page_move.logid),
+            ParsedLogEvent::Deletion(deletion) => (deletion.timestamp, deletion.logid),
+        }
+    }
+}
+
+/// Fetches the entries of a log, e.g. "move", oldest first from `start` included.
+pub async fn fetch_log_events(
+    client: &ApiClient,
+    url: &str,
+    log_type: &str,
+    start: OffsetDateTime,
+    limit: Option<u32>,
+    continue_token: Option<LeContinueToken>,
+    version: &MediaWikiVersion,
+) -> Result<LeApiResult, ApiError> {
+    let limit = limit.unwrap_or(50);
+    let mut params: HashMap<&str, String> = HashMap::new();
+    params.insert("action", "query".to_string());
+    params.insert("format", "json".to_string());
+    params.insert("list", "logevents".to_string());
+    params.insert("letype", log_type.to_string());
+    params.insert(
+        "leprop",
+        "ids|title|type|user|timestamp|comment|details".to_string(),
+    );
+    params.insert("lestart", start.format(&Rfc3339).unwrap());
+    params.insert("ledir", "newer".to_string());
+    params.insert("lelimit", limit.to_string());
+    version.insert_continue(&mut params, continue_token.as_ref());
+
+    client.query(url, &params).await
+}
+
+/// Returns the moves of a response to `fetch_log_events` for the "move" log,
+/// skipping those whose target was hidden.
+pub fn get_parsed_moves(query: LeQueryResult) -> Vec<ParsedMove> {
+    let mut parsed_moves = Vec::new();
+    for event in query.logevents {
+        let Some((new_namespace, new_title)) =
+            get_move_target(event.params.as_ref(), event.legacy_move.as_ref())
+        else {
+            continue;
+        };
+        let new_title = new_title.to_string();
+        parsed_moves.push(ParsedMove {
+            logid: event.logid,
+            pageid: event.logpage,
+            timestamp: PrimitiveDateTime::parse(&event.timestamp, &Rfc3339).unwrap(),
+            user: get_user(event.user, event.userhidden.as_ref()),
+            comment: get_comment(event.comment, event.commenthidden.as_ref()),
+            old_namespace: event.ns,
+            old_title: event.title,
+            new_namespace,
+            new_title,
+        });
+    }
+    parsed_moves
+}
+
+/// Returns the deletions and undeletions of a response to `fetch_log_events` for
+/// the "delete" log, skipping the deletions of single revisions.
+pub fn get_parsed_deletions(query: LeQueryResult) -> Vec<ParsedDeletion> {
+    let mut parsed_deletions = Vec::new();
+    for event in query.logevents {
+        // "delete_redir" is for redirects deleted to move a page over them
+        let restored = match event.action.as_str() {
+            "delete" | "delete_redir" => false,
+            "restore" => true,
+            _ => continue,
+        };
+        parsed_deletions.push(ParsedDeletion {
+            logid: event.logid,
+            pageid: event.logpage,
+            timestamp: PrimitiveDateTime::parse(&event.timestamp, &Rfc3339).unwrap(),
+            user: get_user(event.user, event.userhidden.as_ref()),
+            comment: get_comment(event.comment, event.commenthidden.as_ref()),
+            namespace: event.ns,
+            title: event.title,
+            restored,
+        });
+    }
+    parsed_deletions
+}
+
+fn get_user(user: String, userhidden: Option<&String>) -> Contributor {
+    if userhidden.is_some() {
+        Contributor::Hidden
+    } else {
+        Contributor::User(user)
+    }
+}
+
+fn get_comment(comment: String, commenthidden: Option<&String>) -> String {
+    if commenthidden.is_some() {
+        HIDDEN_COMMENT.to_string()
+    } else {
+        comment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_parsed_moves() {
+        let resp = serde_json::json!({
+            "query": {"logevents": [
+                {"logid": 7, "ns": 0, "title": "Foo", "pageid": 0, "logpage": 1,
+                    "params": {"target_ns": 4, "target_title": "Project:Foo"},
+                    "type": "move", "action": "move", "user": "Bar",
+                    "timestamp": "2023-01-02T00:00:00Z", "comment": "Better name"},
+                {"logid": 8, "ns": 0, "title": "Baz", "pageid": 3, "logpage": 2,
+                    "move": {"new_ns": 0, "new_title": "Qux"},
+                    "type": "move", "action": "move_redir", "userhidden": "",
+                    "timestamp": "2023-01-03T00:00:00Z", "commenthidden": ""}
+            ]}
+        });
+        let resp: LeApiResult = serde_json::from_value(resp).unwrap();
+
+        let moves = get_parsed_moves(resp.query);
+        assert_eq!(
+            moves[0],
+            ParsedMove {
+                logid: 7,
+                pageid: 1,
+                timestamp: datetime!(2023-01-02 00:00:00),
+                user: Contributor::User("Bar".into()),
+                comment: "Better name".into(),
+                old_namespace: 0,
+                old_title: "Foo".into(),
+                new_namespace: 4,
+                new_title: "Project:Foo".into(),
+            }
+        );
+        assert_eq!(moves[1].pageid, 2);
+        assert_eq!(moves[1].user, Contributor::Hidden);
+        assert_eq!(moves[1].comment, HIDDEN_COMMENT);
+        assert_eq!(moves[1].new_title, "Qux");
+    }
+
+    #[test]
+    fn test_parsed_deletions() {
+        let resp = serde_json::json!({
+            "query": {"logevents": [
+                {"logid": 9, "ns": 0, "title": "Foo", "pageid": 0, "logpage": 1, "params": [],
+                    "type": "delete", "action": "delete", "user": "Admin",
+                    "timestamp": "2023-01-04T00:00:00Z", "comment": "Spam"},
+                {"logid": 10, "ns": 0, "title": "Bar", "pageid": 2, "logpage": 2,
+                    "params": {"ids": [13], "type": "revision"},
+                    "type": "delete", "action": "revision", "user": "Admin",
+                    "timestamp": "2023-01-05T00:00:00Z", "comment": ""},
+                {"logid": 11, "ns": 0, "title": "Foo", "pageid": 1, "logpage": 1,
+                    "params": {"count": {"revisions": 2, "files": 0}},
+                    "type": "delete", "action": "restore", "user": "Admin",
+                    "timestamp": "2023-01-06T00:00:00Z", "comment": ""}
+            ]}
+        });
+        let resp: LeApiResult = serde_json::from_value(resp).unwrap();
+
+        let deletions = get_parsed_deletions(resp.query);
+        assert_eq!(deletions.len(), 2);
+        assert_eq!(
+            deletions[0],
+            ParsedDeletion {
+                logid: 9,
+                pageid: 1,
+                timestamp: datetime!(2023-01-04 00:00:00),
+                user: Contributor::User("Admin".into()),
+                comment: "Spam".into(),
+                namespace: 0,
+                title: "Foo".into(),
+                restored: false,
+            }
+        );
+        assert_eq!(deletions[1].logid, 11);
+        assert!(deletions[1].restored);
+    }
+}
@@ -0,0 +1,77 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::api_client::{ApiClient, ApiError};
+
+/// The API doesn't accept more titles per request
+pub const MAX_TITLES: usize = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct PtApiResult {
+    /// Missing if no titles were given
+    pub query: Option<PtQueryResult>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PtQueryResult {
+    /// Keyed by page id, or by a negative number for pages that don't exist
+    pub pages: HashMap<String, PtPage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PtPage {
+    /// Missing if the page doesn't exist
+    pub pageid: Option<u64>,
+    /// Missing if the title is invalid
+    pub ns: Option<u32>,
+    pub title: String,
+    /// Present (and empty) if the title is invalid
+    #[serde(default)]
+    pub invalid: Option<String>,
+    /// Present (and empty) if the page is a redirect
+    #[serde(default)]
+    pub redirect: Option<String>,
+}
+
+/// Fetches the ids and namespaces of up to `MAX_TITLES` pages given by their
+/// full title. Titles come back normalized, e.g. with a capitalized first letter.
+pub async fn fetch_pages_by_title(
+    client: &ApiClient,
+    url: &str,
+    titles: &[String],
+) -> Result<PtApiResult, ApiError> {
+    let mut params: HashMap<&str, String> = HashMap::new();
+    params.insert("action", "query".to_string());
+    params.insert("format", "json".to_string());
+    params.insert("titles", titles.join("|"));
+
+    client.query(url, &params).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pages_by_title() {
+        let resp = serde_json::json!({
+            "batchcomplete": "",
+            "query": {
+                "normalized": [{"from": "foo", "to": "Foo"}],
+                "pages": {
+                    "1": {"pageid": 1, "ns": 0, "title": "Foo", "redirect": ""},
+                    "-1": {"ns": 0, "title": "Bar", "missing": ""},
+                    "-2": {"title": "<", "invalid": "", "invalidreason": "Bad title"}
+                }
+            }
+        });
+        let resp: PtApiResult = serde_json::from_value(resp).unwrap();
+
+        let pages = resp.query.unwrap().pages;
+        assert_eq!(pages["1"].pageid, Some(1));
+        assert_eq!(pages["1"].title, "Foo");
+        assert!(pages["1"].redirect.is_some());
+        assert_eq!(pages["-1"].pageid, None);
+        assert!(pages["-2"].invalid.is_some());
+    }
+}
@@ -0,0 +1,171 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::api_client::{ApiClient, ApiError};
+use crate::fetch_site_info::MediaWikiVersion;
+
+/// Parameters to send back to continue the query, e.g. `rccontinue`
+pub type RcContinueToken = HashMap<String, String>;
+
+#[derive(Debug, Deserialize)]
+pub struct RcApiResult {
+    #[serde(rename = "continue")]
+    pub cont: Option<RcContinueToken>,
+    pub query: RcQueryResult,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RcQueryResult {
+    pub recentchanges: Vec<RecentChange>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecentChange {
+    /// "edit", "new" or "log"
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub ns: u32,
+    pub title: String,
+    /// 0 for log entries about pages that don't exist anymore
+    #[serde(default)]
+    pub pageid: u64,
+    pub timestamp: String,
+    /// Only for log entries, e.g. "move" or "delete"
+    #[serde(default)]
+    pub logtype: Option<String>,
+    /// The parameters of log entries, e.g. the new title of moves
+    #[serde(default)]
+    pub logparams: Option<RcLogParams>,
+    /// The parameters of moves, before MediaWiki 1.25
+    #[serde(rename = "move", default)]
+    pub legacy_move: Option<RcLegacyMove>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RcLogParams {
+    #[serde(default)]
+    pub target_ns: Option<u32>,
+    #[serde(default)]
+    pub target_title: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RcLegacyMove {
+    pub new_ns: u32,
+    pub new_title: String,
+}
+
+impl RecentChange {
+    /// The namespace and title of the page after a move
+    pub fn get_move_target(&self) -> Option<(u32, &str)> {
+        get_move_target(self.logparams.as_ref(), self.legacy_move.as_ref())
+    }
+}
+
+/// The namespace and title of the page after a move, from the parameters of a log
+/// entry in either format
+pub fn get_move_target<'a>(
+    params: Option<&'a RcLogParams>,
+    legacy_move: Option<&'a RcLegacyMove>,
+) -> Option<(u32, &'a str)> {
+    if let Some(RcLogParams {
+        target_ns: Some(ns),
+        target_title: Some(title),
+    }) = params
+    {
+        Some((*ns, title))
+    } else {
+        let legacy_move = legacy_move?;
+        Some((legacy_move.new_ns, &legacy_move.new_title))
+    }
+}
+
+/// Fetches the edits, page creations and log entries of the given namespaces,
+/// oldest first from `start` included, or newest first without one.
+pub async fn fetch_recent_changes(
+    client: &ApiClient,
+    url: &str,
+    start: Option<OffsetDateTime>,
+    namespaces: &[u32],
+    limit: Option<u32>,
+    continue_token: Option<RcContinueToken>,
+    version: &MediaWikiVersion,
+) -> Result<RcApiResult, ApiError> {
+    let limit = limit.unwrap_or(50);
+    let mut params: HashMap<&str, String> = HashMap::new();
+    params.insert("action", "query".to_string());
+    params.insert("format", "json".to_string());
+    params.insert("list", "recentchanges".to_string());
+    params.insert("rcprop", "title|ids|timestamp|loginfo".to_string());
+    params.insert("rctype", "edit|new|log".to_string());
+    params.insert("rclimit", limit.to_string());
+    if !namespaces.is_empty() {
+        params.insert(
+            "rcnamespace",
+            namespaces
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join("|"),
+        );
+    }
+    if let Some(start) = start {
+        params.insert("rcstart", start.format(&Rfc3339).unwrap());
+        params.insert("rcdir", "newer".to_string());
+    }
+    version.insert_continue(&mut params, continue_token.as_ref());
+
+    client.query(url, &params).await
+}
+
+/// Fetches the date of the most recent change in the given namespaces, if any
+pub async fn fetch_last_change(
+    client: &ApiClient,
+    url: &str,
+    namespaces: &[u32],
+    version: &MediaWikiVersion,
+) -> Result<Option<OffsetDateTime>, ApiError> {
+    let resp = fetch_recent_changes(client, url, None, namespaces, Some(1), None, version).await?;
+    Ok(resp
+        .query
+        .recentchanges
+        .first()
+        .map(|change| OffsetDateTime::parse(&change.timestamp, &Rfc3339).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_recent_changes() {
+        let resp = serde_json::json!({
+            "continue": {"rccontinue": "20230103000000|5", "continue": "-||"},
+            "query": {"recentchanges": [
+                {"type": "edit", "ns": 0, "title": "Foo", "pageid": 1, "revid": 13,
+                    "old_revid": 10, "rcid": 3, "timestamp": "2023-01-02T00:00:00Z"},
+                {"type": "log", "ns": 0, "title": "Foo", "pageid": 1, "revid": 0,
+                    "old_revid": 0, "rcid": 4, "timestamp": "2023-01-02T01:00:00Z",
+                    "logid": 2, "logtype": "move", "logaction": "move",
+                    "logparams": {"target_ns": 4, "target_title": "Project:Foo"}},
+                {"type": "log", "ns": 0, "title": "Bar", "pageid": 2, "revid": 0,
+                    "old_revid": 0, "rcid": 5, "timestamp": "2023-01-02T02:00:00Z",
+                    "logid": 3, "logtype": "move", "logaction": "move",
+                    "move": {"new_ns": 0, "new_title": "Baz"}},
+                {"type": "log", "ns": 0, "title": "Qux", "pageid": 0, "revid": 0,
+                    "old_revid": 0, "rcid": 6, "timestamp": "2023-01-02T03:00:00Z",
+                    "logid": 4, "logtype": "delete", "logaction": "delete", "logparams": []}
+            ]}
+        });
+        let resp: RcApiResult = serde_json::from_value(resp).unwrap();
+        assert_eq!(resp.cont.unwrap()["rccontinue"], "20230103000000|5");
+
+        let changes = resp.query.recentchanges;
+        assert_eq!(changes[0].kind, "edit");
+        assert_eq!(changes[0].get_move_target(), None);
+        assert_eq!(changes[1].get_move_target(), Some((4, "Project:Foo")));
+        assert_eq!(changes[2].get_move_target(), Some((0, "Baz")));
+        assert_eq!(changes[3].logtype.as_deref(), Some("delete"));
+    }
+}
@@ -1,13 +1,13 @@
-use reqwest::Error;
 use serde::Deserialize;
 use std::collections::HashMap;
 use time::{format_description::well_known::Rfc3339, OffsetDateTime, PrimitiveDateTime};
 use tracing::warn;
 
-#[derive(Debug, Deserialize)]
-pub struct RvContinueToken {
-    pub rvcontinue: String,
-}
+use crate::api_client::{ApiClient, ApiError};
+use crate::fetch_site_info::MediaWikiVersion;
+
+/// Parameters to send back to continue the query, e.g. `rvcontinue`
+pub type RvContinueToken = HashMap<String, String>;
 
 #[derive(Debug, Deserialize)]
 pub struct RvApiResult {
@@ -30,127 +30,406 @@ pub struct RvPage {
 #[derive(Debug, Default, Deserialize)]
 pub struct Revision {
     pub revid: u64,
+    /// 0 if this is the first revision of the page
+    #[serde(default)]
+    pub parentid: Option<u64>,
     pub timestamp: String,
+    /// Missing if the user was hidden by revision deletion
+    #[serde(default)]
     pub user: String,
+    /// Present (and empty) if the user was hidden by revision deletion
+    #[serde(default)]
+    pub userhidden: Option<String>,
+    /// Present (and empty) if the revision was made by an anonymous user
+    #[serde(default)]
+    pub anon: Option<String>,
+    /// Present (and empty) for minor edits
+    #[serde(default)]
+    pub minor: Option<String>,
+    /// Missing if the comment was hidden by revision deletion
+    #[serde(default)]
     pub comment: String,
+    /// Present (and empty) if the comment was hidden by revision deletion
+    #[serde(default)]
+    pub commenthidden: Option<String>,
+    /// Hexadecimal SHA-1 of the content, missing if it was hidden
+    #[serde(default)]
+    pub sha1: Option<String>,
+    /// Missing before MediaWiki 1.32
+    #[serde(default)]
     pub slots: HashMap<String, RvSlot>,
+    /// The content, before MediaWiki 1.32 which had no slots
+    #[serde(rename = "*")]
+    pub content: Option<String>,
+    /// The content model, before MediaWiki 1.32
+    #[serde(default)]
+    pub contentmodel: Option<String>,
+    /// Present (and empty) if the content was hidden, before MediaWiki 1.32
+    #[serde(default)]
+    pub texthidden: Option<String>,
 }
 
 #[derive(Debug, Default, Deserialize)]
 pub struct RvSlot {
-    #[serde(rename = "*")]
+    /// Missing if the content was hidden by revision deletion
+    #[serde(rename = "*", default)]
     pub content: String,
+    #[serde(default)]
+    pub contentmodel: Option<String>,
+    /// Present (and empty) if the content was hidden by revision deletion
+    #[serde(default)]
+    pub texthidden: Option<String>,
+}
+
+/// Commit message of revisions whose comment was hidden by revision deletion
+pub const HIDDEN_COMMENT: &str = "(edit summary removed)";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Contributor {
+    /// A registered user
+    User(String),
+    /// An anonymous user, identified by their IP address
+    Ip(String),
+    /// The user was hidden by revision deletion
+    Hidden,
+}
+
+impl std::fmt::Display for Contributor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Contributor::User(name) => write!(f, "{}", name),
+            Contributor::Ip(ip) => write!(f, "{}", ip),
+            Contributor::Hidden => write!(f, "(username removed)"),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ParsedRevision {
     pub revid: u64,
+    /// Id of the previous revision of the page if it's known, 0 for the first one
+    pub parentid: Option<u64>,
+    pub pageid: u64,
     pub timestamp: PrimitiveDateTime,
     pub title: String,
-    pub user: String,
+    pub user: Contributor,
+    pub minor: bool,
     pub comment: String,
+    /// Base-36 SHA-1 of the content, as found in dumps
+    pub sha1: Option<String>,
+    /// Content model, e.g. "wikitext" or "css"
+    pub model: Option<String>,
+    /// Content format, e.g. "text/x-wiki"
+    pub format: Option<String>,
+    pub content: String,
+    /// Set if the content was hidden by revision deletion, in which case it's empty
+    pub text_hidden: bool,
+    /// The slots other than "main" of wikis with Multi-Content Revisions, by role
+    pub slots: Vec<ParsedSlot>,
+}
+
+/// A slot of a revision other than "main", e.g. the "mediainfo" of Commons
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsedSlot {
+    pub role: String,
+    /// Content model, e.g. "json" or "wikibase-mediainfo"
+    pub model: Option<String>,
     pub content: String,
 }
 
+/// Fetches revisions of a page, oldest first when `starting_date` or `starting_revid`
+/// is given. `starting_revid` takes precedence; that revision is itself included.
+#[allow(clippy::too_many_arguments)]
 pub async fn fetch_revisions(
-    client: &reqwest::Client,
+    client: &ApiClient,
     url: &str,
     pageid: u64,
     limit: Option<u32>,
     starting_date: Option<OffsetDateTime>,
+    starting_revid: Option<u64>,
     continue_token: Option<RvContinueToken>,
-) -> Result<RvApiResult, Error> {
+    version: &MediaWikiVersion,
+) -> Result<RvApiResult, ApiError> {
     let limit = limit.unwrap_or(5);
     let mut params: HashMap<&str, String> = HashMap::new();
     params.insert("action", "query".to_string());
     params.insert("format", "json".to_string());
     params.insert("prop", "revisions".to_string());
     params.insert("pageids", pageid.to_string());
-    params.insert("rvprop", "ids|timestamp|user|comment|content".to_string());
-    params.insert("rvslots", "*".to_string());
+    params.insert(
+        "rvprop",
+        "ids|timestamp|flags|user|comment|sha1|content|contentmodel".to_string(),
+    );
+    if version.has_slots() {
+        params.insert("rvslots", "*".to_string());
+    }
     params.insert("rvlimit", limit.to_string());
-    if let Some(starting_date) = starting_date {
+    if let Some(starting_revid) = starting_revid {
+        params.insert("rvstartid", starting_revid.to_string());
+        params.insert("rvdir", "newer".to_string());
+    } else if let Some(starting_date) = starting_date {
         if let Ok(starting_date) = starting_date.format(&Rfc3339) {
             params.insert("rvstart", starting_date);
             params.insert("rvdir", "newer".to_string());
         }
     }
-    if let Some(continue_token) = continue_token {
-        params.insert("rvcontinue", continue_token.rvcontinue);
+    if let Some(continue_token) = &continue_token {
+        // Before MediaWiki 1.20, the query continues from an `rvstartid`, which
+        // can't be sent along with `rvstart`
+        if continue_token.contains_key("rvstartid") {
+            params.remove("rvstart");
+        }
     }
+    version.insert_continue(&mut params, continue_token.as_ref());
 
-    let resp = client
-        .get(url)
-        .query(&params)
-        .send()
-        .await?
-        .json::<serde_json::Value>()
-        .await?;
-    Ok(serde_json::from_value(resp).unwrap())
+    client.query(url, &params).await
 }
 
 pub fn get_parsed_revisions(query: RvQueryResult, title: String) -> Vec<ParsedRevision> {
     let mut parsed_revisions = Vec::new();
 
-    for (_, page) in query.pages {
-        for revision in page.revisions {
-            for (name, slot) in revision.slots {
-                if name != "main" {
-                    warn!("Unexpected slot name: '{}'", name);
-                } else {
-                    parsed_revisions.push(ParsedRevision {
-                        revid: revision.revid,
-                        timestamp: PrimitiveDateTime::parse(&revision.timestamp, &Rfc3339).unwrap(),
-                        title: title.clone(),
-                        user: revision.user.clone(),
-                        comment: revision.comment.clone(),
-                        content: slot.content,
-                    });
-                }
+    for (pageid, page) in query.pages {
+        let pageid = pageid.parse().unwrap();
+        for mut revision in page.revisions {
+            if revision.content.is_some() || revision.texthidden.is_some() {
+                revision.slots.insert(
+                    "main".to_string(),
+                    RvSlot {
+                        content: revision.content.take().unwrap_or_default(),
+                        contentmodel: revision.contentmodel.take(),
+                        texthidden: revision.texthidden.take(),
+                    },
+                );
             }
+            let Some(main) = revision.slots.remove("main") else {
+                warn!("Revision {} has no main slot, skipping", revision.revid);
+                continue;
+            };
+            let mut slots = revision
+                .slots
+                .into_iter()
+                .map(|(role, slot)| ParsedSlot {
+                    role,
+                    model: slot.contentmodel,
+                    content: slot.content,
+                })
+                .collect::<Vec<_>>();
+            slots.sort_by(|a, b| a.role.cmp(&b.role));
+
+            parsed_revisions.push(ParsedRevision {
+                revid: revision.revid,
+                parentid: revision.parentid,
+                pageid,
+                timestamp: PrimitiveDateTime::parse(&revision.timestamp, &Rfc3339).unwrap(),
+                title: title.clone(),
+                user: if revision.userhidden.is_some() {
+                    Contributor::Hidden
+                } else if revision.anon.is_some() {
+                    Contributor::Ip(revision.user)
+                } else {
+                    Contributor::User(revision.user)
+                },
+                minor: revision.minor.is_some(),
+                comment: if revision.commenthidden.is_some() {
+                    HIDDEN_COMMENT.to_string()
+                } else {
+                    revision.comment
+                },
+                sha1: revision.sha1.as_deref().and_then(get_base36_sha1),
+                model: main.contentmodel,
+                format: None,
+                content: main.content,
+                text_hidden: main.texthidden.is_some(),
+                slots,
+            });
         }
     }
 
     parsed_revisions
 }
 
+/// Returns the SHA-1 of a text in the base-36 form of dumps.
+pub fn get_text_sha1(text: &str) -> String {
+    let hex = openssl::sha::sha1(text.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    get_base36_sha1(&hex).unwrap()
+}
+
+/// Converts a hexadecimal SHA-1, as the API returns it, to the base-36 form of
+/// dumps, padded to 31 digits like MediaWiki does.
+fn get_base36_sha1(hex: &str) -> Option<String> {
+    let mut number = hex
+        .chars()
+        .map(|c| c.to_digit(16))
+        .collect::<Option<Vec<_>>>()?;
+    let mut digits = Vec::new();
+    while !number.is_empty() {
+        // Long division of the number by 36, one hexadecimal digit at a time
+        let mut quotient = Vec::new();
+        let mut remainder = 0;
+        for digit in number {
+            let value = remainder * 16 + digit;
+            if !quotient.is_empty() || value >= 36 {
+                quotient.push(value / 36);
+            }
+            remainder = value % 36;
+        }
+        digits.push(char::from_digit(remainder, 36).unwrap());
+        number = quotient;
+    }
+    let padding = "0".repeat(31usize.saturating_sub(digits.len()));
+    Some(padding + &digits.iter().rev().collect::<String>())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::api_client::ClientOptions;
     use insta::assert_debug_snapshot;
     use time::macros::datetime;
 
     #[tokio::test]
     async fn test_fetch_content() {
-        let client = reqwest::Client::new();
+        let client = ApiClient::new(ClientOptions::default());
+        let version = MediaWikiVersion::default();
         let url = "https://wiki.archlinux.org/api.php".to_string();
 
         // Page "EXWM"
         let pageid = 24908;
-        let resp = fetch_revisions(&client, &url, pageid, Some(2), None, None)
+        let resp = fetch_revisions(&client, &url, pageid, Some(2), None, None, None, &version)
             .await
             .unwrap();
         assert_debug_snapshot!(resp.query.pages.values().next().unwrap());
         assert_debug_snapshot!(get_parsed_revisions(resp.query, "EXWM".into()));
 
-        let resp = fetch_revisions(&client, &url, pageid, Some(2), None, resp.cont)
-            .await
-            .unwrap();
+        let resp = fetch_revisions(
+            &client,
+            &url,
+            pageid,
+            Some(2),
+            None,
+            None,
+            resp.cont,
+            &version,
+        )
+        .await
+        .unwrap();
         assert_debug_snapshot!(resp.query.pages.values().next().unwrap());
         assert_debug_snapshot!(get_parsed_revisions(resp.query, "EXWM".into()));
     }
 
+    #[test]
+    fn test_parsed_legacy_revisions() {
+        // MediaWiki 1.31, without slots
+        let resp = serde_json::json!({
+            "continue": {"rvcontinue": "20230102000000|11"},
+            "query": {"pages": {"1": {"pageid": 1, "ns": 0, "title": "Foo", "revisions": [{
+                "revid": 10, "parentid": 0, "user": "Bar", "minor": "",
+                "timestamp": "2023-01-01T00:00:00Z", "comment": "Create",
+                "sha1": "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+                "contentformat": "text/x-wiki", "contentmodel": "css", "*": "body {}"
+            }]}}}
+        });
+        let resp: RvApiResult = serde_json::from_value(resp).unwrap();
+        assert_eq!(resp.cont.unwrap()["rvcontinue"], "20230102000000|11");
+
+        let revisions = get_parsed_revisions(resp.query, "Foo".into());
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].revid, 10);
+        assert_eq!(revisions[0].parentid, Some(0));
+        assert!(revisions[0].minor);
+        assert_eq!(
+            revisions[0].sha1.as_deref(),
+            Some("phoiac9h4m842xq45sp7s6u21eteeq1")
+        );
+        assert_eq!(revisions[0].model.as_deref(), Some("css"));
+        assert_eq!(revisions[0].content, "body {}");
+
+        assert_eq!(get_text_sha1(""), "phoiac9h4m842xq45sp7s6u21eteeq1");
+        assert_eq!(
+            get_text_sha1("Hello, world\n"),
+            "eeeto0wulu1xo64cx8w79hx995hr5q0"
+        );
+    }
+
+    #[test]
+    fn test_parsed_slots() {
+        let resp = serde_json::json!({
+            "query": {"pages": {"1": {"pageid": 1, "ns": 6, "title": "File:Foo.png", "revisions": [{
+                "revid": 10, "parentid": 0, "user": "Bar",
+                "timestamp": "2023-01-01T00:00:00Z", "comment": "Caption",
+                "slots": {
+                    "mediainfo": {"contentmodel": "wikibase-mediainfo", "*": "{\"id\":\"M1\"}"},
+                    "main": {"contentmodel": "wikitext", "*": "Hello"}
+                }
+            }]}}}
+        });
+        let resp: RvApiResult = serde_json::from_value(resp).unwrap();
+
+        let revisions = get_parsed_revisions(resp.query, "File:Foo.png".into());
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].content, "Hello");
+        assert_eq!(revisions[0].model.as_deref(), Some("wikitext"));
+        assert_eq!(
+            revisions[0].slots,
+            [ParsedSlot {
+                role: "mediainfo".into(),
+                model: Some("wikibase-mediainfo".into()),
+                content: "{\"id\":\"M1\"}".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parsed_hidden_revisions() {
+        let resp = serde_json::json!({
+            "query": {"pages": {"1": {"pageid": 1, "ns": 0, "title": "Foo", "revisions": [{
+                "revid": 10, "parentid": 0, "userhidden": "",
+                "timestamp": "2023-01-01T00:00:00Z", "commenthidden": "",
+                "slots": {"main": {"contentmodel": "wikitext", "texthidden": ""}}
+            }, {
+                "revid": 11, "parentid": 10, "user": "Bar",
+                "timestamp": "2023-01-02T00:00:00Z", "comment": "Edit",
+                "texthidden": "", "contentmodel": "wikitext"
+            }]}}}
+        });
+        let resp: RvApiResult = serde_json::from_value(resp).unwrap();
+
+        let revisions = get_parsed_revisions(resp.query, "Foo".into());
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].user, Contributor::Hidden);
+        assert_eq!(revisions[0].comment, HIDDEN_COMMENT);
+        assert!(revisions[0].text_hidden);
+        assert_eq!(revisions[0].content, "");
+        // Before MediaWiki 1.32
+        assert_eq!(revisions[1].user, Contributor::User("Bar".into()));
+        assert_eq!(revisions[1].comment, "Edit");
+        assert!(revisions[1].text_hidden);
+    }
+
     #[tokio::test]
     async fn test_fetch_after() {
-        let client = reqwest::Client::new();
+        let client = ApiClient::new(ClientOptions::default());
+        let version = MediaWikiVersion::default();
         let url = "https://wiki.archlinux.org/api.php".to_string();
 
         // Page "EXWM"
         let pageid = 24908;
         let datetime = datetime!(2021-01-01 00:00:00 +00:00);
-        let resp = fetch_revisions(&client, &url, pageid, Some(2), Some(datetime), None)
-            .await
-            .unwrap();
+        let resp = fetch_revisions(
+            &client,
+            &url,
+            pageid,
+            Some(2),
+            Some(datetime),
+            None,
+            None,
+            &version,
+        )
+        .await
+        .unwrap();
         assert_debug_snapshot!(resp.query.pages.values().next().unwrap());
         assert_debug_snapshot!(get_parsed_revisions(resp.query, "EXWM".into()));
     }
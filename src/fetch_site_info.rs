@@ -0,0 +1,216 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::warn;
+
+use crate::api_client::{ApiClient, ApiError};
+use crate::parse_xml_dump::{NamespaceInfo, NamespaceList, SiteInfo};
+
+#[derive(Debug, Deserialize)]
+pub struct SiApiResult {
+    pub query: SiQueryResult,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SiQueryResult {
+    pub general: SiGeneral,
+    pub namespaces: HashMap<String, SiNamespace>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SiGeneral {
+    pub sitename: String,
+    #[serde(default)]
+    pub wikiid: String,
+    pub base: String,
+    pub generator: String,
+    pub case: String,
+    #[serde(default)]
+    pub script: String,
+    #[serde(default)]
+    pub articlepath: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SiNamespace {
+    pub id: i32,
+    pub case: String,
+    #[serde(rename = "*")]
+    pub name: String,
+}
+
+impl From<SiQueryResult> for SiteInfo {
+    fn from(query: SiQueryResult) -> Self {
+        let mut namespaces: Vec<_> = query
+            .namespaces
+            .into_values()
+            .map(|namespace| NamespaceInfo {
+                key: namespace.id,
+                case: namespace.case,
+                name: namespace.name,
+            })
+            .collect();
+        namespaces.sort_by_key(|namespace| namespace.key);
+
+        SiteInfo {
+            sitename: query.general.sitename,
+            dbname: query.general.wikiid,
+            base: query.general.base,
+            generator: query.general.generator,
+            case: query.general.case,
+            namespaces: NamespaceList { namespaces },
+            script: Some(query.general.script).filter(|script| !script.is_empty()),
+            article_path: Some(query.general.articlepath).filter(|path| !path.is_empty()),
+        }
+    }
+}
+
+/// The MediaWiki version of a wiki, which decides the shape of our requests
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MediaWikiVersion {
+    /// Major and minor version, unknown for wikis that hide it, which we treat
+    /// as recent
+    version: Option<(u32, u32)>,
+}
+
+impl MediaWikiVersion {
+    /// Reads the version from the `generator` of siteinfo, e.g. "MediaWiki 1.31.0"
+    pub fn from_generator(generator: &str) -> Self {
+        let version = generator.strip_prefix("MediaWiki ").and_then(|version| {
+            let mut numbers = version
+                .split(|c: char| !c.is_ascii_digit())
+                .map(|number| number.parse().ok());
+            Some((numbers.next()??, numbers.next()??))
+        });
+        if version.is_none() {
+            warn!("Unknown MediaWiki version '{}'", generator);
+        }
+        Self { version }
+    }
+
+    /// Revisions have slots since MediaWiki 1.32; before that, the content
+    /// is on the revision itself
+    pub fn has_slots(&self) -> bool {
+        self.is_at_least(1, 32)
+    }
+
+    /// Adds the parameters that continue a query, if any. MediaWiki 1.21 to 1.25
+    /// only use the `continue` format, which we parse, when asked for.
+    /// Older ones only have `query-continue`, which `ApiClient` turns into it.
+    pub fn insert_continue<'a>(
+        &self,
+        params: &mut HashMap<&'a str, String>,
+        continue_token: Option<&'a HashMap<String, String>>,
+    ) {
+        match continue_token {
+            Some(continue_token) => {
+                for (key, value) in continue_token {
+                    params.insert(key, value.clone());
+                }
+            }
+            None if self.is_at_least(1, 21) && !self.is_at_least(1, 26) => {
+                params.insert("continue", String::new());
+            }
+            None => {}
+        }
+    }
+
+    fn is_at_least(&self, major: u32, minor: u32) -> bool {
+        self.version.is_none_or(|version| version >= (major, minor))
+    }
+}
+
+/// Fetches the same site information that XML dumps start with
+pub async fn fetch_site_info(client: &ApiClient, url: &str) -> Result<SiteInfo, ApiError> {
+    let mut params: HashMap<&str, String> = HashMap::new();
+    params.insert("action", "query".to_string());
+    params.insert("format", "json".to_string());
+    params.insert("meta", "siteinfo".to_string());
+    params.insert("siprop", "general|namespaces".to_string());
+
+    let resp: SiApiResult = client.query(url, &params).await?;
+    Ok(resp.query.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_client::ClientOptions;
+
+    #[test]
+    fn test_media_wiki_version() {
+        let version = MediaWikiVersion::from_generator("MediaWiki 1.41.0");
+        assert!(version.has_slots());
+
+        let version = MediaWikiVersion::from_generator("MediaWiki 1.31.16");
+        assert!(!version.has_slots());
+
+        let version = MediaWikiVersion::from_generator("MediaWiki 1.23.0-wmf.5");
+        assert_eq!(version.version, Some((1, 23)));
+        let mut params = HashMap::new();
+        version.insert_continue(&mut params, None);
+        assert_eq!(params["continue"], "");
+
+        let version = MediaWikiVersion::from_generator("MediaWiki 1.19.2");
+        let mut params = HashMap::new();
+        version.insert_continue(&mut params, None);
+        assert!(params.is_empty());
+        let continue_token = HashMap::from([("rvstartid".to_string(), "42".to_string())]);
+        version.insert_continue(&mut params, Some(&continue_token));
+        assert_eq!(params["rvstartid"], "42");
+
+        let version = MediaWikiVersion::from_generator("Some wiki");
+        assert_eq!(version, MediaWikiVersion::default());
+        assert!(version.has_slots());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_site_info() {
+        let client = ApiClient::new(ClientOptions::default());
+        let url = "https://wiki.archlinux.org/api.php".to_string();
+
+        let site_info = fetch_site_info(&client, &url).await.unwrap();
+        assert_eq!(site_info.sitename, "ArchWiki");
+        assert_eq!(site_info.dbname, "archwiki");
+        assert_eq!(site_info.namespace_name(0), Some(""));
+        assert_eq!(site_info.namespace_name(3), Some("User talk"));
+        assert_eq!(site_info.namespaces.namespaces[0].key, -2);
+    }
+
+    #[test]
+    fn test_site_info_from_api_result() {
+        let resp = serde_json::json!({
+            "batchcomplete": "",
+            "query": {
+                "general": {
+                    "mainpage": "Wikipedia:Hauptseite",
+                    "base": "https://de.wikipedia.org/wiki/Wikipedia:Hauptseite",
+                    "sitename": "Wikipedia",
+                    "generator": "MediaWiki 1.41.0-wmf.25",
+                    "case": "first-letter",
+                    "wikiid": "dewiki",
+                    "script": "/w/index.php",
+                    "articlepath": "/wiki/$1"
+                },
+                "namespaces": {
+                    "2": {"id": 2, "case": "first-letter", "*": "Benutzer", "canonical": "User"},
+                    "0": {"id": 0, "case": "first-letter", "*": "", "content": ""},
+                    "-1": {"id": -1, "case": "first-letter", "*": "Spezial", "canonical": "Special"}
+                }
+            }
+        });
+        let resp: SiApiResult = serde_json::from_value(resp).unwrap();
+        let site_info = SiteInfo::from(resp.query);
+
+        assert_eq!(site_info.dbname, "dewiki");
+        assert_eq!(site_info.script.as_deref(), Some("/w/index.php"));
+        assert_eq!(site_info.article_path.as_deref(), Some("/wiki/$1"));
+        assert_eq!(site_info.namespace_name(2), Some("Benutzer"));
+        let keys: Vec<_> = site_info
+            .namespaces
+            .namespaces
+            .iter()
+            .map(|namespace| namespace.key)
+            .collect();
+        assert_eq!(keys, vec![-1, 0, 2]);
+    }
+}
@@ -0,0 +1,175 @@
+use reqwest::Url;
+use serde_json::Value;
+use std::collections::HashMap;
+use tracing::{debug, info};
+
+use crate::api_client::{ApiClient, ApiError};
+
+/// Finds the `api.php` endpoint of a wiki from any of its URLs, e.g. its base URL
+/// or the URL of an article.
+///
+/// The `EditURI` link of the page is tried first, then the usual locations next
+/// to the URL and its parents. Each candidate is confirmed with a single
+/// `meta=siteinfo` query, which isn't retried and doesn't log in, so that bad
+/// guesses fail fast and credentials only go to the confirmed API.
+pub async fn find_api_url(client: &ApiClient, wiki_url: &str) -> Result<String, ApiError> {
+    let Ok(url) = Url::parse(wiki_url) else {
+        return Err(ApiError::ApiNotFound {
+            url: wiki_url.to_string(),
+        });
+    };
+
+    let mut candidates = Vec::new();
+    if url.path().ends_with("/api.php") {
+        candidates.push(url.clone());
+    } else {
+        match client.download(url.as_str()).await {
+            Ok(page) => candidates.extend(get_edit_uri(&String::from_utf8_lossy(&page), &url)),
+            Err(error) => debug!("Could not fetch {}: {}", url, error),
+        }
+    }
+    for candidate in get_usual_api_urls(&url) {
+        if !candidates.contains(&candidate) {
+            candidates.push(candidate);
+        }
+    }
+
+    let mut params: HashMap<&str, String> = HashMap::new();
+    params.insert("action", "query".to_string());
+    params.insert("format", "json".to_string());
+    params.insert("meta", "siteinfo".to_string());
+    params.insert("siprop", "general".to_string());
+    for candidate in candidates {
+        match client.probe::<Value>(candidate.as_str(), &params).await {
+            Ok(value) if value["query"]["general"]["sitename"].is_string() => {
+                info!(
+                    "Found the API of '{}' at {}",
+                    value["query"]["general"]["sitename"].as_str().unwrap(),
+                    candidate
+                );
+                return Ok(candidate.to_string());
+            }
+            Ok(_) => debug!("No API at {}: not a siteinfo response", candidate),
+            // Wikis that are only readable once logged in still answer with an
+            // API error, e.g. "readapidenied"
+            Err(ApiError::Api { code, .. }) => {
+                info!("Found an API at {}, which answered '{}'", candidate, code);
+                return Ok(candidate.to_string());
+            }
+            Err(error) => debug!("No API at {}: {}", candidate, error),
+        }
+    }
+    Err(ApiError::ApiNotFound {
+        url: wiki_url.to_string(),
+    })
+}
+
+/// Reads the API URL from the `<link rel="EditURI">` tag that MediaWiki adds to
+/// every page, which points to `api.php?action=rsd`.
+fn get_edit_uri(html: &str, page_url: &Url) -> Option<Url> {
+    html.split("<link")
+        .skip(1)
+        .filter_map(|tag| tag.split('>').next())
+        .filter(|tag| get_attribute(tag, "rel") == Some("EditURI"))
+        .find_map(|tag| {
+            // Relative and protocol-relative URLs are common
+            let mut url = page_url.join(get_attribute(tag, "href")?).ok()?;
+            url.set_query(None);
+            url.set_fragment(None);
+            Some(url)
+        })
+}
+
+fn get_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!("{}=", name))? + name.len() + 1;
+    let quote = tag[start..]
+        .chars()
+        .next()
+        .filter(|c| *c == '"' || *c == '\'')?;
+    let value = &tag[start + 1..];
+    value.split(quote).next()
+}
+
+/// `api.php` and `w/api.php` next to the URL and each of its parents, deepest first.
+fn get_usual_api_urls(url: &Url) -> Vec<Url> {
+    let mut directories = Vec::new();
+    let mut path = url.path().trim_end_matches('/');
+    loop {
+        directories.push(format!("{}/", path));
+        match path.rfind('/') {
+            Some(index) => path = &path[..index],
+            None => break,
+        }
+    }
+
+    let mut urls = Vec::new();
+    for directory in directories {
+        for file in ["api.php", "w/api.php"] {
+            let mut candidate = url.clone();
+            candidate.set_path(&format!("{}{}", directory, file));
+            candidate.set_query(None);
+            candidate.set_fragment(None);
+            urls.push(candidate);
+        }
+    }
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_edit_uri() {
+        let page_url = Url::parse("https://en.wikipedia.org/wiki/Git").unwrap();
+        let html = r#"<head>
+<link rel="stylesheet" href="/w/load.php?modules=site.styles">
+<link rel="EditURI" type="application/rsd+xml" href="//en.wikipedia.org/w/api.php?action=rsd">
+</head>"#;
+        assert_eq!(
+            get_edit_uri(html, &page_url).unwrap().as_str(),
+            "https://en.wikipedia.org/w/api.php"
+        );
+
+        let html = r#"<link rel='EditURI' href='/mywiki/api.php?action=rsd'/>"#;
+        assert_eq!(
+            get_edit_uri(html, &page_url).unwrap().as_str(),
+            "https://en.wikipedia.org/mywiki/api.php"
+        );
+
+        assert!(get_edit_uri("<html></html>", &page_url).is_none());
+    }
+
+    #[test]
+    fn test_get_usual_api_urls() {
+        let url = Url::parse("https://example.org/wiki/Main_Page?action=view").unwrap();
+        let urls = get_usual_api_urls(&url)
+            .iter()
+            .map(Url::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            [
+                "https://example.org/wiki/Main_Page/api.php",
+                "https://example.org/wiki/Main_Page/w/api.php",
+                "https://example.org/wiki/api.php",
+                "https://example.org/wiki/w/api.php",
+                "https://example.org/api.php",
+                "https://example.org/w/api.php",
+            ]
+        );
+
+        let url = Url::parse("https://wiki.archlinux.org").unwrap();
+        let urls = get_usual_api_urls(&url)
+            .iter()
+            .map(Url::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            [
+                "https://wiki.archlinux.org/api.php",
+                "https://wiki.archlinux.org/w/api.php"
+            ]
+        );
+    }
+}
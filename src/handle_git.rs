@@ -1,15 +1,23 @@
 #![allow(unused_imports)]
 
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 
 use git2::build::CheckoutBuilder;
-use git2::{AnnotatedCommit, BranchType, Commit, Repository, Signature};
+use git2::{
+    AnnotatedCommit, BranchType, Commit, ConfigLevel, Delta, IndexEntry, IndexTime, ObjectType,
+    Oid, Repository, Signature, Sort, Tree, TreeWalkMode, TreeWalkResult,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, PrimitiveDateTime};
 use tracing::{debug, debug_span, info_span, trace};
 use tracing_subscriber::field::debug;
-use urlencoding::encode;
+use urlencoding::{decode, encode};
 
+use crate::convert_file::is_wikitext;
+use crate::fetch_log_events::{ParsedDeletion, ParsedMove};
 use crate::fetch_revisions::{ParsedRevision, Revision};
 use crate::get_author_data::{Author, AuthorData};
+use crate::parse_xml_dump::{ParsedUpload, SiteInfo};
 
 pub fn create_repo(path: &str, committer: &Signature<'_>) -> Result<Repository, git2::Error> {
     let repo = git2::Repository::init(path).unwrap();
@@ -19,7 +27,7 @@ pub fn create_repo(path: &str, committer: &Signature<'_>) -> Result<Repository,
     let commit_id = repo
         .commit(
             Some("HEAD"),
-            &committer,
+            committer,
             committer,
             "Initial commit",
             &repo.find_tree(tree_id).unwrap(),
@@ -33,11 +41,46 @@ pub fn create_repo(path: &str, committer: &Signature<'_>) -> Result<Repository,
     Ok(repo)
 }
 
+/// Opens the repository so that symbolic links are checked out as files holding
+/// their target, like on systems without them, as checking out a file over a link
+/// would write through it. File modes are ignored too, or these files would differ
+/// from the index.
+///
+/// The settings only apply to the returned `Repository`: they are read from a file
+/// of the temporary directory, and the configuration of the repository is left as is.
+pub fn open_repo_without_symlinks(path: &Path) -> Result<Repository, git2::Error> {
+    let config_path = std::env::temp_dir().join("convert_wiki_symlinks.gitconfig");
+    std::fs::write(
+        &config_path,
+        "[core]\n\tsymlinks = false\n\tfilemode = false\n",
+    )
+    .map_err(|error| git2::Error::from_str(&error.to_string()))?;
+
+    // The repository caches these settings once it used them, so it has to be new
+    let repository = Repository::open(path)?;
+    repository
+        .config()?
+        .add_file(&config_path, ConfigLevel::App, true)?;
+
+    // Links checked out since, e.g. with `git checkout`, are replaced by files
+    // with the next checkout
+    let workdir = repository.workdir().unwrap().to_path_buf();
+    for entry in repository.index()?.iter() {
+        let file_path = workdir.join(String::from_utf8_lossy(&entry.path).as_ref());
+        if entry.mode == 0o120000 && file_path.is_symlink() {
+            trace!("removing symbolic link at {:?}", file_path);
+            std::fs::remove_file(&file_path)
+                .map_err(|error| git2::Error::from_str(&error.to_string()))?;
+        }
+    }
+    Ok(repository)
+}
+
 pub fn create_branch(repository: &Repository, base_name: &str, branch_name: &str) {
     trace!("Creating branch '{}'", branch_name);
     repository
         .branch(
-            &branch_name,
+            branch_name,
             &repository
                 .revparse_single(base_name)
                 .unwrap()
@@ -48,12 +91,182 @@ pub fn create_branch(repository: &Repository, base_name: &str, branch_name: &str
         .unwrap();
 }
 
-pub fn get_signature<'a>(revision: &'a ParsedRevision, author_info: &'a Author) -> Signature<'a> {
-    let time = revision.timestamp.assume_utc().unix_timestamp();
+pub fn get_signature<'a>(timestamp: PrimitiveDateTime, author_info: &'a Author) -> Signature<'a> {
+    let time = timestamp.assume_utc().unix_timestamp();
     let time = git2::Time::new(time, 0);
     Signature::new(&author_info.name, &author_info.email, &time).unwrap()
 }
 
+/// Builds the message of the commit for a revision, with the ids of the revision
+/// and the page as trailers so that commits can be matched with the wiki.
+/// Revisions whose content was hidden are marked, since their commits may be empty.
+pub fn get_commit_message(revision: &ParsedRevision) -> String {
+    let mut message = format!(
+        "{}\n\nWiki-Revision-Id: {}\nWiki-Page-Id: {}",
+        revision.comment, revision.revid, revision.pageid
+    );
+    if revision.text_hidden {
+        message.push_str("\nWiki-Text-Hidden: true");
+    }
+    message
+}
+
+/// Builds the message of the commit for a page move, with the ids of the log entry
+/// and the page as trailers.
+pub fn get_move_commit_message(page_move: &ParsedMove) -> String {
+    let mut message = format!(
+        "Moved page '{}' to '{}'",
+        page_move.old_title, page_move.new_title
+    );
+    if !page_move.comment.is_empty() {
+        message = format!("{}: {}", message, page_move.comment);
+    }
+    format!(
+        "{}\n\nWiki-Log-Id: {}\nWiki-Page-Id: {}",
+        message, page_move.logid, page_move.pageid
+    )
+}
+
+/// Builds the message of the commit for a page deletion or undeletion: the reason
+/// given for it, with the ids of the log entry and the page as trailers.
+pub fn get_deletion_commit_message(deletion: &ParsedDeletion) -> String {
+    let message = match (deletion.comment.is_empty(), deletion.restored) {
+        (false, _) => deletion.comment.clone(),
+        (true, false) => format!("Deleted page '{}'", deletion.title),
+        (true, true) => format!("Restored page '{}'", deletion.title),
+    };
+    format!(
+        "{}\n\nWiki-Log-Id: {}\nWiki-Page-Id: {}",
+        message, deletion.logid, deletion.pageid
+    )
+}
+
+/// Builds the message of the commit for a version of an uploaded file. The upload
+/// timestamp is used instead of a revision id to match it with the wiki.
+pub fn get_upload_commit_message(upload: &ParsedUpload) -> String {
+    format!(
+        "{}\n\nWiki-Upload-Timestamp: {}\nWiki-Page-Id: {}",
+        upload.comment,
+        upload.timestamp.assume_utc().format(&Rfc3339).unwrap(),
+        upload.pageid
+    )
+}
+
+/// Returns the value of a trailer added by `get_commit_message` or
+/// `get_upload_commit_message`.
+fn get_trailer<'a>(message: &'a str, key: &str) -> Option<&'a str> {
+    // Trailers are at the end, after anything the edit summary may contain
+    for line in message.lines().rev() {
+        if line.is_empty() {
+            break;
+        }
+        let value = line
+            .strip_prefix(key)
+            .and_then(|line| line.strip_prefix(": "));
+        if let Some(value) = value {
+            return Some(value.trim());
+        }
+    }
+    None
+}
+
+/// Returns the edit summary a commit message was built from, without the trailers
+/// added by `get_commit_message` or `get_upload_commit_message`.
+pub fn get_commit_comment(message: &str) -> &str {
+    match message.rsplit_once("\n\n") {
+        Some((comment, trailers))
+            if trailers
+                .lines()
+                .all(|line| line.is_empty() || line.starts_with("Wiki-")) =>
+        {
+            comment
+        }
+        _ => message.trim_end(),
+    }
+}
+
+/// Reads the revision and page ids back from the trailers added by `get_commit_message`.
+fn parse_commit_ids(message: &str) -> Option<(u64, u64)> {
+    let revid = get_trailer(message, "Wiki-Revision-Id")?.parse().ok()?;
+    let pageid = get_trailer(message, "Wiki-Page-Id")?.parse().ok()?;
+    Some((revid, pageid))
+}
+
+/// Reads the upload timestamp and page id back from the trailers added by
+/// `get_upload_commit_message`.
+fn parse_upload_commit_ids(message: &str) -> Option<(PrimitiveDateTime, u64)> {
+    let timestamp = get_trailer(message, "Wiki-Upload-Timestamp")?;
+    let timestamp = PrimitiveDateTime::parse(timestamp, &Rfc3339).ok()?;
+    let pageid = get_trailer(message, "Wiki-Page-Id")?.parse().ok()?;
+    Some((timestamp, pageid))
+}
+
+/// Key of the repository configuration holding the date of the most recent change
+/// imported by the last sync
+const LAST_SYNC_KEY: &str = "convert-wiki.lastsync";
+
+/// Returns the date the next sync starts from, if the repository was synced before
+pub fn get_last_sync(repository: &Repository) -> Option<OffsetDateTime> {
+    let last_sync = repository.config().ok()?.get_string(LAST_SYNC_KEY).ok()?;
+    OffsetDateTime::parse(&last_sync, &Rfc3339).ok()
+}
+
+pub fn set_last_sync(repository: &Repository, date: OffsetDateTime) -> Result<(), git2::Error> {
+    repository
+        .config()?
+        .set_str(LAST_SYNC_KEY, &date.format(&Rfc3339).unwrap())
+}
+
+/// Returns the id of the most recent revision committed for each page, by page id.
+///
+/// This is how conversions resume: a repository seeded from a dump and later
+/// synced through the API only ever imports revisions newer than these.
+pub fn get_imported_revisions(repository: &Repository) -> Result<HashMap<u64, u64>, git2::Error> {
+    let _span = info_span!("get_imported_revisions").entered();
+
+    let mut revwalk = repository.revwalk()?;
+    revwalk.push_glob("refs/heads/*")?;
+
+    let mut imported_revisions = HashMap::new();
+    for oid in revwalk {
+        let commit = repository.find_commit(oid?)?;
+        if let Some((revid, pageid)) = commit.message().and_then(parse_commit_ids) {
+            let last_revid = imported_revisions.entry(pageid).or_insert(revid);
+            *last_revid = revid.max(*last_revid);
+        }
+    }
+    debug!(
+        "Found imported revisions for {} pages",
+        imported_revisions.len()
+    );
+    Ok(imported_revisions)
+}
+
+/// Returns the timestamp of the most recent upload committed for each file page,
+/// by page id.
+pub fn get_imported_uploads(
+    repository: &Repository,
+) -> Result<HashMap<u64, PrimitiveDateTime>, git2::Error> {
+    let _span = info_span!("get_imported_uploads").entered();
+
+    let mut revwalk = repository.revwalk()?;
+    revwalk.push_glob("refs/heads/*")?;
+
+    let mut imported_uploads = HashMap::new();
+    for oid in revwalk {
+        let commit = repository.find_commit(oid?)?;
+        if let Some((timestamp, pageid)) = commit.message().and_then(parse_upload_commit_ids) {
+            let last_timestamp = imported_uploads.entry(pageid).or_insert(timestamp);
+            *last_timestamp = timestamp.max(*last_timestamp);
+        }
+    }
+    debug!(
+        "Found imported uploads for {} pages",
+        imported_uploads.len()
+    );
+    Ok(imported_uploads)
+}
+
 fn swallow_already_applied<T>(res: Result<T, git2::Error>) -> Result<(), git2::Error> {
     match res {
         Ok(_) => Ok(()),
@@ -65,6 +278,36 @@ fn swallow_already_applied<T>(res: Result<T, git2::Error>) -> Result<(), git2::E
     }
 }
 
+/// Whether the commit is that of a revision whose content was hidden, and doesn't
+/// change anything compared to its parent
+fn is_hidden_text_commit(repository: &Repository, id: Oid) -> Result<bool, git2::Error> {
+    let commit = repository.find_commit(id)?;
+    let is_hidden_text =
+        get_trailer(commit.message().unwrap_or_default(), "Wiki-Text-Hidden").is_some();
+    Ok(is_hidden_text
+        && commit.parent_count() == 1
+        && commit.tree_id() == commit.parent(0)?.tree_id())
+}
+
+/// Copies the commit on top of HEAD, with the tree of HEAD
+fn commit_again(
+    repository: &Repository,
+    id: Oid,
+    committer: &Signature<'_>,
+) -> Result<Oid, git2::Error> {
+    let commit = repository.find_commit(id)?;
+    let author = commit.author();
+    let head = repository.head()?.peel_to_commit()?;
+    repository.commit(
+        Some("HEAD"),
+        &author,
+        committer,
+        commit.message().unwrap_or_default(),
+        &head.tree()?,
+        &[&head],
+    )
+}
+
 pub fn clean_files(repository: &Repository) {
     trace!("cleaning files");
     let mut checkout_builder = CheckoutBuilder::new();
@@ -81,7 +324,8 @@ pub fn create_commit_from_metadata(
     committer: Signature<'_>,
     author: Signature<'_>,
     branch_name: &str,
-    file_path: &Path,
+    file_paths: &[&Path],
+    symlink_paths: &[&Path],
     comment: &str,
 ) {
     let _span = info_span!("create_commit_from_metadata", branch_name).entered();
@@ -92,12 +336,34 @@ pub fn create_commit_from_metadata(
         .peel_to_commit()
         .unwrap();
 
-    // stage changes to file at file_path
-    trace!("staging changes to file at {:?}", file_path);
+    let workdir = repository.workdir().unwrap().to_path_buf();
     let mut index = repository.index().unwrap();
-    index.add_path(file_path).unwrap();
+    // Start from the branch's tree, since the index matches whatever HEAD is
+    index.read_tree(&parent.tree().unwrap()).unwrap();
+    for file_path in file_paths {
+        // stage changes to file at file_path, or its removal if it was deleted
+        if workdir.join(file_path).exists() {
+            trace!("staging changes to file at {:?}", file_path);
+            // Without core.symlinks, a file replacing a symbolic link would keep its
+            // mode, so the old entry is removed first
+            index.remove_path(file_path).unwrap();
+            index.add_path(file_path).unwrap();
+            if symlink_paths.contains(file_path) {
+                // The file holds the target of the symbolic link
+                let mut entry = index.get_path(file_path, 0).unwrap();
+                entry.mode = 0o120000;
+                index.add(&entry).unwrap();
+            }
+        } else {
+            trace!("staging removal of file at {:?}", file_path);
+            index.remove_path(file_path).unwrap();
+        }
+    }
 
-    if index.is_empty() {
+    // Null edits don't change anything. Without files to stage, the commit only
+    // records the author and message, e.g. of a revision whose content was hidden
+    let tree_id = index.write_tree().unwrap();
+    if tree_id == parent.tree_id() && !file_paths.is_empty() {
         trace!("no changes to commit");
         return;
     }
@@ -109,7 +375,7 @@ pub fn create_commit_from_metadata(
             &author,
             &committer,
             comment,
-            &repository.find_tree(index.write_tree().unwrap()).unwrap(),
+            &repository.find_tree(tree_id).unwrap(),
             &[&parent],
         )
         .unwrap();
@@ -117,6 +383,101 @@ pub fn create_commit_from_metadata(
     clean_files(repository);
 }
 
+/// Commits moves of files of a branch, from the first path of each pair to the
+/// second, without going through the working directory.
+pub fn create_move_commit(
+    repository: &Repository,
+    committer: &Signature<'_>,
+    author: &Signature<'_>,
+    branch_name: &str,
+    moved_file_names: &[(PathBuf, PathBuf)],
+    comment: &str,
+) -> Result<Oid, git2::Error> {
+    let _span = info_span!("create_move_commit", branch_name).entered();
+
+    let parent = get_most_recent_commit(repository, branch_name)?;
+    let mut index = repository.index()?;
+    index.read_tree(&parent.tree()?)?;
+    for (file_path, new_file_path) in moved_file_names {
+        trace!("moving file at {:?} to {:?}", file_path, new_file_path);
+        let Some(mut entry) = index.get_path(file_path, 0) else {
+            continue;
+        };
+        index.remove_path(file_path)?;
+        // If the page was also imported under its new title, that file is more recent
+        if index.get_path(new_file_path, 0).is_none() {
+            entry.path = new_file_path.to_string_lossy().as_bytes().to_vec();
+            index.add(&entry)?;
+        }
+    }
+
+    let tree = repository.find_tree(index.write_tree()?)?;
+    let id = repository.commit(
+        Some(&format!("refs/heads/{}", branch_name)),
+        author,
+        committer,
+        comment,
+        &tree,
+        &[&parent],
+    )?;
+    clean_files(repository);
+    Ok(id)
+}
+
+/// Commits the removal of files of a branch or, given the tree of an earlier commit,
+/// their restoration to the version they had there.
+pub fn create_deletion_commit(
+    repository: &Repository,
+    committer: &Signature<'_>,
+    author: &Signature<'_>,
+    branch_name: &str,
+    file_paths: &[PathBuf],
+    restored_tree: Option<&Tree<'_>>,
+    comment: &str,
+) -> Result<Oid, git2::Error> {
+    let _span = info_span!("create_deletion_commit", branch_name).entered();
+
+    let parent = get_most_recent_commit(repository, branch_name)?;
+    let mut index = repository.index()?;
+    index.read_tree(&parent.tree()?)?;
+    for file_path in file_paths {
+        if index.get_path(file_path, 0).is_some() {
+            trace!("removing file at {:?}", file_path);
+            index.remove_path(file_path)?;
+        }
+        let Some(tree_entry) = restored_tree.and_then(|tree| tree.get_path(file_path).ok()) else {
+            continue;
+        };
+        trace!("restoring file at {:?}", file_path);
+        index.add(&IndexEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: tree_entry.filemode() as u32,
+            uid: 0,
+            gid: 0,
+            file_size: 0,
+            id: tree_entry.id(),
+            flags: 0,
+            flags_extended: 0,
+            path: file_path.to_string_lossy().as_bytes().to_vec(),
+        })?;
+    }
+
+    let tree = repository.find_tree(index.write_tree()?)?;
+    let id = repository.commit(
+        Some(&format!("refs/heads/{}", branch_name)),
+        author,
+        committer,
+        comment,
+        &tree,
+        &[&parent],
+    )?;
+    clean_files(repository);
+    Ok(id)
+}
+
 pub fn get_most_recent_commit<'a>(
     repository: &'a Repository,
     branch_name: &str,
@@ -160,12 +521,23 @@ pub fn rebase_branch(
         match op {
             Ok(operation) => {
                 trace!("rebase operation: {:?}", operation);
-                let res = rebase.commit(None, committer, None);
-                // We skip "commit already applied" errors. I'm not sure why some
-                // of them happen, but in any case, some of them will happen because
-                // this program is meant to be resumable, and resuming it will produce
-                // some duplicate commits.
-                swallow_already_applied(res)?;
+                let id = operation.id();
+                match rebase.commit(None, committer, None) {
+                    // The empty commits of revisions whose content was hidden are
+                    // kept rather than dropped, unlike any other empty commit
+                    Err(e)
+                        if e.code() == git2::ErrorCode::Applied
+                            && is_hidden_text_commit(repository, id)? =>
+                    {
+                        trace!("keeping empty commit {}", id);
+                        commit_again(repository, id, committer)?;
+                    }
+                    // We skip "commit already applied" errors. I'm not sure why some
+                    // of them happen, but in any case, some of them will happen because
+                    // this program is meant to be resumable, and resuming it will produce
+                    // some duplicate commits.
+                    res => swallow_already_applied(res)?,
+                }
             }
             Err(err) => {
                 trace!("rebase error: {:?}", err);
@@ -179,7 +551,7 @@ pub fn rebase_branch(
 
     // set upstream to result of rebase
     repository.branch(
-        &upstream_name,
+        upstream_name,
         &repository
             .revparse_single(branch_name)
             .unwrap()
@@ -191,38 +563,546 @@ pub fn rebase_branch(
     Ok(())
 }
 
-pub fn get_file_name(page_name: &str, namespace: u32) -> PathBuf {
-    let page_name = page_name.replace("_", "__");
-    let page_name = page_name.replace(" ", "_");
-    let page_name = encode(&page_name);
+/// Namespace of uploaded files, which is the same on every wiki
+pub const FILE_NAMESPACE: u32 = 6;
+
+/// Splits a page title into its namespace prefix and the rest of the title.
+///
+/// Pages of the main namespace go in "Main". For other namespaces, the localized
+/// name from the site info is used; if the namespace isn't known, we assume the
+/// title has the form "Prefix:Name".
+fn split_title<'a>(
+    page_name: &'a str,
+    namespace: u32,
+    site_info: &'a SiteInfo,
+) -> (&'a str, &'a str) {
     if namespace == 0 {
-        format!("Main/{page_name}.md").into()
-    } else if namespace != 6 {
-        // The page name will be something like "User:Foo"
-        // which encode transforms into "User%3AFoo"
-        PathBuf::from(page_name.replacen("%3A", "/", 1)).with_extension("md")
+        return ("Main", page_name);
+    }
+    match site_info.namespace_name(namespace) {
+        Some(namespace_name) if !namespace_name.is_empty() => {
+            let name = page_name
+                .strip_prefix(namespace_name)
+                .and_then(|name| name.strip_prefix(':'))
+                // Titles can use an alias of the namespace, e.g. a gendered one
+                .or_else(|| page_name.split_once(':').map(|(_, name)| name))
+                .unwrap_or(page_name);
+            (namespace_name, name)
+        }
+        _ => page_name.split_once(':').unwrap_or(("", page_name)),
+    }
+}
+
+fn escape_file_name(name: &str) -> String {
+    let name = name.replace('_', "__");
+    let name = name.replace(' ', "_");
+    encode(&name).into_owned()
+}
+
+pub fn get_file_name(page_name: &str, namespace: u32, site_info: &SiteInfo) -> PathBuf {
+    let (prefix, name) = split_title(page_name, namespace, site_info);
+    let name = format!("{}.md", escape_file_name(name));
+    if prefix.is_empty() {
+        PathBuf::from(name)
     } else {
-        // namespace == 6 for the File namespace,
-        // for which we don't want to change the extension
-        PathBuf::from(page_name.replacen("%3A", "/", 1))
+        PathBuf::from(escape_file_name(prefix)).join(name)
     }
 }
 
-pub fn get_branch_name(page_name: &str, namespace: u32) -> String {
-    let page_name = if namespace == 0 {
-        format!("Main:{}", page_name)
+/// Returns the path of a file relative to the directory of another, e.g.
+/// "../Help/Foo.md" from "Main/Bar.md".
+pub fn get_relative_path(from_file_path: &Path, to_file_path: &Path) -> PathBuf {
+    let directory = from_file_path.parent().unwrap_or(Path::new(""));
+    if to_file_path.parent() == Some(directory) {
+        return PathBuf::from(to_file_path.file_name().unwrap());
+    }
+    directory
+        .components()
+        .map(|_| Path::new(".."))
+        .chain([to_file_path])
+        .collect()
+}
+
+/// Path of the map from the files redirect pages would have to the files of their
+/// target, for `--redirects map`
+pub const REDIRECT_MAP_FILE: &str = "redirects.json";
+
+/// Reads the map of redirects of a branch, which is empty if it has none.
+pub fn read_redirect_map(
+    repository: &Repository,
+    branch_name: &str,
+) -> Result<BTreeMap<String, String>, git2::Error> {
+    let tree = get_most_recent_commit(repository, branch_name)?.tree()?;
+    let Ok(entry) = tree.get_path(Path::new(REDIRECT_MAP_FILE)) else {
+        return Ok(BTreeMap::new());
+    };
+    let blob = repository.find_blob(entry.id())?;
+    Ok(serde_json::from_slice(blob.content()).unwrap_or_default())
+}
+
+/// Commits the map of redirects to a branch if it changed. The commit gets the
+/// date of the previous one, which dates the last change imported from the wiki.
+pub fn commit_redirect_map(
+    repository: &Repository,
+    committer: &Signature<'_>,
+    branch_name: &str,
+    redirect_map: &BTreeMap<String, String>,
+) -> Result<(), git2::Error> {
+    let _span = info_span!("commit_redirect_map", branch_name).entered();
+
+    if read_redirect_map(repository, branch_name)? == *redirect_map {
+        trace!("no changes to the map of redirects");
+        return Ok(());
+    }
+    let parent = get_most_recent_commit(repository, branch_name)?;
+    let content = format!("{}\n", serde_json::to_string_pretty(redirect_map).unwrap());
+    let blob_id = repository.blob(content.as_bytes())?;
+    let mut tree_builder = repository.treebuilder(Some(&parent.tree()?))?;
+    tree_builder.insert(REDIRECT_MAP_FILE, blob_id, 0o100644)?;
+    let tree = repository.find_tree(tree_builder.write()?)?;
+
+    let author = Signature::new(
+        committer.name().unwrap(),
+        committer.email().unwrap(),
+        &parent.author().when(),
+    )?;
+    repository.commit(
+        Some(&format!("refs/heads/{}", branch_name)),
+        &author,
+        committer,
+        "Update the map of redirects",
+        &tree,
+        &[&parent],
+    )?;
+    clean_files(repository);
+    Ok(())
+}
+
+/// Returns the extension of files with content of the given model.
+pub fn get_content_model_extension(model: Option<&str>) -> &'static str {
+    match model {
+        Some("wikitext") => "wiki",
+        Some("css" | "sanitized-css") => "css",
+        Some("javascript") => "js",
+        Some("json" | "JsonSchema" | "Json.JsonConfig") => "json",
+        Some(model) if model.starts_with("wikibase-") => "json",
+        Some("Scribunto") => "lua",
+        _ => "txt",
+    }
+}
+
+/// Returns the content model of files with the given extension, the reverse of
+/// `get_content_model_extension`.
+pub fn get_extension_content_model(extension: &str) -> Option<&'static str> {
+    match extension {
+        "wiki" => Some("wikitext"),
+        "css" => Some("css"),
+        "js" => Some("javascript"),
+        "json" => Some("json"),
+        "lua" => Some("Scribunto"),
+        "txt" => Some("text"),
+        _ => None,
+    }
+}
+
+/// Returns the path of a slot other than "main" of a page, next to the file of
+/// the page, e.g. "File/Foo.png.mediainfo.json" for "File/Foo.png.md".
+pub fn get_slot_file_name(file_path: &Path, role: &str, model: Option<&str>) -> PathBuf {
+    file_path.with_file_name(format!(
+        "{}{}.{}",
+        get_slot_file_prefix(file_path),
+        escape_file_name(role),
+        get_content_model_extension(model)
+    ))
+}
+
+/// The start of the names of the slot files of a page: the name of its file,
+/// without the extension of Markdown files.
+fn get_slot_file_prefix(file_path: &Path) -> String {
+    let file_name = file_path.file_name().unwrap().to_string_lossy();
+    format!("{}.", file_name.strip_suffix(".md").unwrap_or(&file_name))
+}
+
+/// Finds the role and content model of a slot file of a page, as named by
+/// `get_slot_file_name`.
+pub fn get_slot_role(file_path: &Path, slot_file_path: &Path) -> Option<(String, &'static str)> {
+    let file_name = slot_file_path.file_name()?.to_str()?;
+    let (role, extension) = file_name
+        .strip_prefix(&get_slot_file_prefix(file_path))?
+        .split_once('.')?;
+    Some((
+        unescape_file_name(role),
+        get_extension_content_model(extension)?,
+    ))
+}
+
+/// Lists the slot files of a page that a branch currently has, as named by
+/// `get_slot_file_name`.
+pub fn get_slot_file_names(
+    repository: &Repository,
+    branch_name: &str,
+    file_path: &Path,
+) -> Result<Vec<PathBuf>, git2::Error> {
+    let tree = get_most_recent_commit(repository, branch_name)?.tree()?;
+    get_tree_slot_file_names(repository, tree, file_path)
+}
+
+fn get_tree_slot_file_names(
+    repository: &Repository,
+    tree: Tree<'_>,
+    file_path: &Path,
+) -> Result<Vec<PathBuf>, git2::Error> {
+    let directory = file_path.parent().unwrap_or(Path::new(""));
+    let directory = if directory.as_os_str().is_empty() {
+        tree
+    } else {
+        match tree.get_path(directory) {
+            Ok(entry) => entry.to_object(repository)?.peel_to_tree()?,
+            Err(_) => return Ok(Vec::new()),
+        }
+    };
+
+    let prefix = get_slot_file_prefix(file_path);
+    let slot_file_names = directory
+        .iter()
+        .filter_map(|entry| entry.name().map(str::to_string))
+        .filter(|name| {
+            let Some((role, extension)) = name
+                .strip_prefix(&prefix)
+                .and_then(|name| name.split_once('.'))
+            else {
+                return false;
+            };
+            !role.is_empty() && ["wiki", "css", "js", "json", "lua", "txt"].contains(&extension)
+        })
+        .map(|name| file_path.with_file_name(name))
+        .collect();
+    Ok(slot_file_names)
+}
+
+/// Returns the path of a page with the given content model. Wikitext pages are
+/// converted to Markdown files, while other pages keep their content and get a
+/// matching extension, e.g. "MediaWiki/Common.css" or "Module/Foo.lua".
+pub fn get_page_file_name(
+    page_name: &str,
+    namespace: u32,
+    model: Option<&str>,
+    site_info: &SiteInfo,
+) -> PathBuf {
+    if is_wikitext(model) {
+        return get_file_name(page_name, namespace, site_info);
+    }
+    let (prefix, name) = split_title(page_name, namespace, site_info);
+    let extension = format!(".{}", get_content_model_extension(model));
+    let mut name = escape_file_name(name);
+    // Titles of CSS, JavaScript and JSON pages usually end with it already
+    if !name.ends_with(&extension) {
+        name.push_str(&extension);
+    }
+    if prefix.is_empty() {
+        PathBuf::from(name)
+    } else {
+        PathBuf::from(escape_file_name(prefix)).join(name)
+    }
+}
+
+/// Finds the file of a page in the tip of a branch, whatever its content model,
+/// and returns it with a content model that gives the same extension.
+pub fn find_page_file(
+    repository: &Repository,
+    branch_name: &str,
+    page_name: &str,
+    namespace: u32,
+    site_info: &SiteInfo,
+) -> Result<Option<(PathBuf, Option<&'static str>)>, git2::Error> {
+    let tree = get_most_recent_commit(repository, branch_name)?.tree()?;
+    Ok(find_tree_page_file(&tree, page_name, namespace, site_info))
+}
+
+fn find_tree_page_file(
+    tree: &Tree<'_>,
+    page_name: &str,
+    namespace: u32,
+    site_info: &SiteInfo,
+) -> Option<(PathBuf, Option<&'static str>)> {
+    let models = [
+        None,
+        Some("css"),
+        Some("javascript"),
+        Some("json"),
+        Some("Scribunto"),
+        Some("text"),
+    ];
+    models.into_iter().find_map(|model| {
+        let file_path = get_page_file_name(page_name, namespace, model, site_info);
+        tree.get_path(&file_path).ok().map(|_| (file_path, model))
+    })
+}
+
+/// Returns the files of a page in a tree: its file, its slot files and, for file
+/// pages, its upload.
+pub fn get_page_file_names(
+    repository: &Repository,
+    tree: &Tree<'_>,
+    file_path: &Path,
+    page_name: &str,
+    namespace: u32,
+    site_info: &SiteInfo,
+) -> Result<Vec<PathBuf>, git2::Error> {
+    let mut file_names = vec![file_path.to_path_buf()];
+    file_names.extend(get_tree_slot_file_names(
+        repository,
+        tree.clone(),
+        file_path,
+    )?);
+    if namespace == FILE_NAMESPACE {
+        let upload_file_path = get_upload_file_name(page_name, site_info);
+        if tree.get_path(&upload_file_path).is_ok() {
+            file_names.push(upload_file_path);
+        }
+    }
+    Ok(file_names)
+}
+
+/// Finds the most recent commit of a branch that has the file of a page, e.g. the
+/// one before the page was deleted, and returns its tree with the files of the page.
+pub fn find_last_page_files<'a>(
+    repository: &'a Repository,
+    branch_name: &str,
+    page_name: &str,
+    namespace: u32,
+    site_info: &SiteInfo,
+) -> Result<Option<(Tree<'a>, Vec<PathBuf>)>, git2::Error> {
+    let mut commit = get_most_recent_commit(repository, branch_name)?;
+    loop {
+        let tree = commit.tree()?;
+        if let Some((file_path, _)) = find_tree_page_file(&tree, page_name, namespace, site_info) {
+            let file_names = get_page_file_names(
+                repository, &tree, &file_path, page_name, namespace, site_info,
+            )?;
+            return Ok(Some((tree, file_names)));
+        }
+        let Ok(parent) = commit.parent(0) else {
+            return Ok(None);
+        };
+        commit = parent;
+    }
+}
+
+/// Returns the new paths of the files of a page that moves from `file_path` to
+/// `new_file_path`: its file, its slot files and, for file pages, its upload.
+pub fn get_moved_file_names(
+    repository: &Repository,
+    branch_name: &str,
+    file_path: &Path,
+    new_file_path: &Path,
+    page_move: &ParsedMove,
+    site_info: &SiteInfo,
+) -> Result<Vec<(PathBuf, PathBuf)>, git2::Error> {
+    let mut moved_file_names = vec![(file_path.to_path_buf(), new_file_path.to_path_buf())];
+
+    let prefix = get_slot_file_prefix(file_path);
+    let new_prefix = get_slot_file_prefix(new_file_path);
+    for slot_file_path in get_slot_file_names(repository, branch_name, file_path)? {
+        let file_name = slot_file_path.file_name().unwrap().to_string_lossy();
+        let suffix = file_name.strip_prefix(&prefix).unwrap();
+        let new_slot_file_path = new_file_path.with_file_name(format!("{}{}", new_prefix, suffix));
+        moved_file_names.push((slot_file_path, new_slot_file_path));
+    }
+
+    if page_move.old_namespace == FILE_NAMESPACE && page_move.new_namespace == FILE_NAMESPACE {
+        let tree = get_most_recent_commit(repository, branch_name)?.tree()?;
+        let upload_file_path = get_upload_file_name(&page_move.old_title, site_info);
+        if tree.get_path(&upload_file_path).is_ok() {
+            let new_upload_file_path = get_upload_file_name(&page_move.new_title, site_info);
+            moved_file_names.push((upload_file_path, new_upload_file_path));
+        }
+    }
+    Ok(moved_file_names)
+}
+
+/// Returns the id of the page whose commit last changed a file of a branch, as
+/// recorded by the trailers of `get_commit_message`, if any.
+pub fn get_file_page_id(
+    repository: &Repository,
+    branch_name: &str,
+    file_path: &Path,
+) -> Result<Option<u64>, git2::Error> {
+    let mut commit = get_most_recent_commit(repository, branch_name)?;
+    loop {
+        let blob_id = commit
+            .tree()?
+            .get_path(file_path)
+            .ok()
+            .map(|entry| entry.id());
+        let Ok(parent) = commit.parent(0) else {
+            return Ok(None);
+        };
+        let parent_blob_id = parent
+            .tree()?
+            .get_path(file_path)
+            .ok()
+            .map(|entry| entry.id());
+        if blob_id != parent_blob_id {
+            let pageid = commit
+                .message()
+                .and_then(|message| get_trailer(message, "Wiki-Page-Id"))
+                .and_then(|pageid| pageid.parse().ok());
+            return Ok(pageid);
+        }
+        commit = parent;
+    }
+}
+
+/// Returns the path of an uploaded file, next to its description page.
+///
+/// Unlike `get_file_name`, this keeps the original extension of the file.
+pub fn get_upload_file_name(page_name: &str, site_info: &SiteInfo) -> PathBuf {
+    let (prefix, name) = split_title(page_name, FILE_NAMESPACE, site_info);
+    let name = escape_file_name(name);
+    if prefix.is_empty() {
+        PathBuf::from(name)
     } else {
-        page_name.to_string()
+        PathBuf::from(escape_file_name(prefix)).join(name)
+    }
+}
+
+fn unescape_file_name(name: &str) -> String {
+    let name = decode(name).map_or_else(|_| name.to_string(), |name| name.into_owned());
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '_' if chars.peek() == Some(&'_') => {
+                chars.next();
+                unescaped.push('_');
+            }
+            '_' => unescaped.push(' '),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Finds the title and namespace of the page a file was written for by
+/// `get_file_name`. Returns `None` for files that aren't pages, like uploads.
+pub fn get_page_title(file_path: &Path, site_info: &SiteInfo) -> Option<(String, u32)> {
+    let file_name = file_path.file_name()?.to_str()?.strip_suffix(".md")?;
+    get_title(file_path.parent()?, file_name, site_info)
+}
+
+/// Finds the title, namespace and content model of the page a file was written
+/// for by `get_page_file_name`, with `None` as the model of wikitext pages.
+///
+/// The titles of CSS, JavaScript and JSON pages are assumed to end with the
+/// extension of their file, as they usually do, and those of other pages not to.
+pub fn get_page_file_title(
+    file_path: &Path,
+    site_info: &SiteInfo,
+) -> Option<(String, u32, Option<&'static str>)> {
+    let file_name = file_path.file_name()?.to_str()?;
+    let (name, model) = match file_name.rsplit_once('.')? {
+        (name, "md") => (name, None),
+        (_, "css") => (file_name, Some("css")),
+        (_, "js") => (file_name, Some("javascript")),
+        (_, "json") => (file_name, Some("json")),
+        (name, "lua") => (name, Some("Scribunto")),
+        (name, "txt") => (name, Some("text")),
+        _ => return None,
+    };
+    let (title, namespace) = get_title(file_path.parent()?, name, site_info)?;
+    Some((title, namespace, model))
+}
+
+fn get_title(directory: &Path, file_name: &str, site_info: &SiteInfo) -> Option<(String, u32)> {
+    let name = unescape_file_name(file_name);
+    let prefix = match directory.to_str()? {
+        "" => return Some((name, 0)),
+        prefix => unescape_file_name(prefix),
+    };
+    if prefix == "Main" {
+        return Some((name, 0));
+    }
+
+    let namespace = site_info
+        .namespaces
+        .namespaces
+        .iter()
+        .find(|info| !info.name.is_empty() && info.name == prefix)
+        .and_then(|info| u32::try_from(info.key).ok());
+    match namespace {
+        Some(namespace) => Some((format!("{}:{}", prefix, name), namespace)),
+        None => {
+            debug!("Unknown namespace '{}', assuming the main one", prefix);
+            Some((format!("{}:{}", prefix, name), 0))
+        }
+    }
+}
+
+/// Lists the commits of a branch that changed each of the files it currently has,
+/// oldest first.
+pub fn get_file_histories(
+    repository: &Repository,
+    branch_name: &str,
+) -> Result<BTreeMap<PathBuf, Vec<Oid>>, git2::Error> {
+    let _span = info_span!("get_file_histories", branch_name).entered();
+
+    let tip = get_most_recent_commit(repository, branch_name)?;
+    let mut histories = BTreeMap::new();
+    tip.tree()?
+        .walk(TreeWalkMode::PreOrder, |directory, entry| {
+            if entry.kind() == Some(ObjectType::Blob) {
+                if let Some(name) = entry.name() {
+                    histories.insert(Path::new(directory).join(name), Vec::new());
+                }
+            }
+            TreeWalkResult::Ok
+        })?;
+
+    let mut revwalk = repository.revwalk()?;
+    revwalk.push(tip.id())?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    for oid in revwalk {
+        let commit = repository.find_commit(oid?)?;
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        let diff =
+            repository.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+        for delta in diff.deltas() {
+            if delta.status() == Delta::Deleted {
+                continue;
+            }
+            let history = delta
+                .new_file()
+                .path()
+                .and_then(|path| histories.get_mut(path));
+            if let Some(history) = history {
+                history.push(commit.id());
+            }
+        }
+    }
+    Ok(histories)
+}
+
+pub fn get_branch_name(page_name: &str, namespace: u32, site_info: &SiteInfo) -> String {
+    let page_name = match split_title(page_name, namespace, site_info) {
+        ("", name) => name.to_string(),
+        (prefix, name) => format!("{}:{}", prefix, name),
     };
     let page_name = encode(&page_name);
-    let page_name = page_name.replace(".", "%2E");
-    page_name
+
+    page_name.replace('.', "%2E")
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
+    use crate::api_client::{ApiClient, ClientOptions};
     use crate::fetch_revisions::{fetch_revisions, get_parsed_revisions};
+    use crate::fetch_site_info::MediaWikiVersion;
+    use crate::parse_xml_dump::{NamespaceInfo, NamespaceList};
 
     /// Removes directory if it exists
     fn clean_dir(dir: &str) {
@@ -232,6 +1112,16 @@ mod tests {
         }
     }
 
+    /// Creates a repository with `create_repo` in the temporary directory, replacing
+    /// the one of a previous run, and returns it with its path and committer
+    pub fn create_test_repo(name: &str) -> (Repository, PathBuf, Signature<'static>) {
+        let repository_path = std::env::temp_dir().join(name);
+        clean_dir(repository_path.to_str().unwrap());
+        let committer = Signature::new("test", "test", &git2::Time::new(0, 0)).unwrap();
+        let repository = create_repo(repository_path.to_str().unwrap(), &committer).unwrap();
+        (repository, repository_path, committer)
+    }
+
     #[test]
     fn test_create_repo() {
         clean_dir("test_create_repo");
@@ -257,14 +1147,23 @@ mod tests {
 
         println!("pwd: {:?}", std::env::current_dir().unwrap());
 
-        let client = reqwest::Client::new();
+        let client = ApiClient::new(ClientOptions::default());
         let url = "https://wiki.archlinux.org/api.php".to_string();
 
         // Page "EXWM"
         let pageid = 24908;
-        let resp = fetch_revisions(&client, &url, pageid, Some(2), None, None)
-            .await
-            .unwrap();
+        let resp = fetch_revisions(
+            &client,
+            &url,
+            pageid,
+            Some(2),
+            None,
+            None,
+            None,
+            &MediaWikiVersion::default(),
+        )
+        .await
+        .unwrap();
         let revisions = get_parsed_revisions(resp.query, "EXWM".into());
         let revision = revisions.first().unwrap();
         println!("revision: {:?}", revision);
@@ -293,7 +1192,7 @@ mod tests {
             name: "name".into(),
             email: "email@example.com".into(),
         };
-        let author = get_signature(revision, &author_info);
+        let author = get_signature(revision.timestamp, &author_info);
         let committer = Signature::new("test", "test", &git2::Time::new(0, 0)).unwrap();
 
         create_commit_from_metadata(
@@ -301,8 +1200,9 @@ mod tests {
             committer,
             author,
             "test_branch",
-            &Path::new("test_file.md"),
-            "Commit message".into(),
+            &[Path::new("test_file.md")],
+            &[],
+            "Commit message",
         );
 
         assert!(std::fs::metadata("test_create_commit/.git")
@@ -326,14 +1226,160 @@ mod tests {
         assert_eq!(commit.message().unwrap(), "Commit message");
     }
 
+    #[test]
+    fn test_last_sync() {
+        let (repository, repository_path, _) = create_test_repo("test_last_sync");
+        assert_eq!(get_last_sync(&repository), None);
+
+        let date = time::macros::datetime!(2023-01-02 03:04:05 UTC);
+        set_last_sync(&repository, date).unwrap();
+        assert_eq!(get_last_sync(&repository), Some(date));
+        std::fs::remove_dir_all(&repository_path).unwrap();
+    }
+
+    #[test]
+    fn test_get_commit_message() {
+        let revision = ParsedRevision {
+            revid: 764858,
+            parentid: Some(753668),
+            pageid: 1007,
+            timestamp: time::macros::datetime!(2023-01-23 08:34:52),
+            title: "Frequently asked questions".into(),
+            user: crate::fetch_revisions::Contributor::User("Erus Iluvatar".into()),
+            minor: true,
+            comment: "Fix typo".into(),
+            sha1: None,
+            model: None,
+            format: None,
+            content: String::new(),
+            text_hidden: false,
+            slots: Vec::new(),
+        };
+        assert_eq!(
+            get_commit_message(&revision),
+            "Fix typo\n\nWiki-Revision-Id: 764858\nWiki-Page-Id: 1007"
+        );
+        assert_eq!(
+            parse_commit_ids(&get_commit_message(&revision)),
+            Some((764858, 1007))
+        );
+        // Commits made before the trailers were added have no ids
+        assert_eq!(parse_commit_ids("Fix typo"), None);
+
+        let revision = ParsedRevision {
+            text_hidden: true,
+            ..revision
+        };
+        let message = get_commit_message(&revision);
+        assert_eq!(get_trailer(&message, "Wiki-Text-Hidden"), Some("true"));
+        assert_eq!(get_commit_comment(&message), "Fix typo");
+        assert_eq!(parse_commit_ids(&message), Some((764858, 1007)));
+    }
+
+    #[test]
+    fn test_get_commit_comment() {
+        assert_eq!(
+            get_commit_comment("Fix typo\n\nWiki-Revision-Id: 764858\nWiki-Page-Id: 1007"),
+            "Fix typo"
+        );
+        assert_eq!(
+            get_commit_comment("\n\nWiki-Revision-Id: 1\nWiki-Page-Id: 2"),
+            ""
+        );
+        assert_eq!(get_commit_comment("Initial commit"), "Initial commit");
+        assert_eq!(
+            get_commit_comment("Summary\n\nMore details\n"),
+            "Summary\n\nMore details"
+        );
+    }
+
+    #[test]
+    fn test_get_page_title() {
+        let site_info = german_site_info();
+        for (title, namespace) in [
+            ("Hallo: Welt_mit__Strichen", 0),
+            ("Benutzer Diskussion:Foo", 3),
+            ("Datei:foobar.png", 6),
+        ] {
+            let file_name = get_file_name(title, namespace, &site_info);
+            assert_eq!(
+                get_page_title(&file_name, &site_info),
+                Some((title.to_string(), namespace))
+            );
+        }
+        assert_eq!(
+            get_page_title(Path::new("Datei/foobar.png"), &site_info),
+            None
+        );
+    }
+
+    #[test]
+    fn test_get_page_file_title() {
+        let site_info = german_site_info();
+        for (title, namespace, model) in [
+            ("Hallo Welt", 0, None),
+            ("Benutzer:Foo/common.css", 2, Some("css")),
+            ("Benutzer:Foo/common.js", 2, Some("javascript")),
+            ("Benutzer:Foo/data.json", 2, Some("json")),
+            ("Foo", 0, Some("Scribunto")),
+            ("Foo", 0, Some("text")),
+        ] {
+            let file_name = get_page_file_name(title, namespace, model, &site_info);
+            assert_eq!(
+                get_page_file_title(&file_name, &site_info),
+                Some((title.to_string(), namespace, model))
+            );
+        }
+        assert_eq!(
+            get_page_file_title(Path::new("Datei/foobar.png"), &site_info),
+            None
+        );
+
+        let file_path = Path::new("Datei/foobar.png.md");
+        let slot_file_path =
+            get_slot_file_name(file_path, "media info", Some("wikibase-mediainfo"));
+        assert_eq!(
+            get_slot_role(file_path, &slot_file_path),
+            Some(("media info".to_string(), "json"))
+        );
+        assert_eq!(
+            get_slot_role(file_path, Path::new("Datei/foobar.png")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_get_upload_commit_message() {
+        let upload = ParsedUpload {
+            pageid: 42,
+            title: "File:Foo.png".into(),
+            timestamp: time::macros::datetime!(2023-01-23 08:34:52),
+            user: crate::fetch_revisions::Contributor::Ip("127.0.0.1".into()),
+            comment: "Wiki-Revision-Id: 1".into(),
+            sha1: None,
+            contents: crate::parse_xml_dump::UploadContents::Missing,
+        };
+        let message = get_upload_commit_message(&upload);
+        assert_eq!(
+            message,
+            "Wiki-Revision-Id: 1\n\nWiki-Upload-Timestamp: 2023-01-23T08:34:52Z\nWiki-Page-Id: 42"
+        );
+        assert_eq!(
+            parse_upload_commit_ids(&message),
+            Some((upload.timestamp, 42))
+        );
+        // Trailer-like lines of the comment aren't trailers
+        assert_eq!(parse_commit_ids(&message), None);
+    }
+
     #[test]
     fn test_get_file_name() {
         assert_eq!(
-            get_file_name("Hello world!", 0).to_string_lossy(),
+            get_file_name("Hello world!", 0, &SiteInfo::default()).to_string_lossy(),
             "Main/Hello_world%21.md"
         );
         assert_eq!(
-            get_file_name("FOO_BAR BAZ", 0).to_string_lossy(),
+            get_file_name("FOO_BAR BAZ", 0, &SiteInfo::default()).to_string_lossy(),
             "Main/FOO__BAR_BAZ.md"
         );
     }
@@ -341,7 +1387,7 @@ mod tests {
     #[test]
     fn test_get_branch_name() {
         assert_eq!(
-            get_branch_name("Hello world.", 0),
+            get_branch_name("Hello world.", 0, &SiteInfo::default()),
             "Main%3AHello%20world%2E".to_string()
         );
     }
@@ -349,7 +1395,7 @@ mod tests {
     #[test]
     fn test_get_file_name_usertalk_namespace() {
         assert_eq!(
-            get_file_name("User Talk:Hello world!", 3).to_string_lossy(),
+            get_file_name("User Talk:Hello world!", 3, &SiteInfo::default()).to_string_lossy(),
             "User_Talk/Hello_world%21.md"
         );
     }
@@ -357,16 +1403,369 @@ mod tests {
     #[test]
     fn test_get_file_name_file_namespace() {
         assert_eq!(
-            get_file_name("File:foobar.png", 6).to_string_lossy(),
+            get_file_name("File:foobar.png", 6, &SiteInfo::default()).to_string_lossy(),
+            "File/foobar.png.md"
+        );
+        // The file itself sits next to its description page
+        assert_eq!(
+            get_upload_file_name("File:foobar.png", &SiteInfo::default()).to_string_lossy(),
             "File/foobar.png"
         );
     }
 
+    #[test]
+    fn test_get_page_file_name() {
+        let site_info = SiteInfo::default();
+        assert_eq!(
+            get_page_file_name("Hello world!", 0, Some("wikitext"), &site_info).to_string_lossy(),
+            "Main/Hello_world%21.md"
+        );
+        assert_eq!(
+            get_page_file_name("MediaWiki:Common.css", 8, Some("css"), &site_info)
+                .to_string_lossy(),
+            "MediaWiki/Common.css"
+        );
+        assert_eq!(
+            get_page_file_name("Module:Arguments", 828, Some("Scribunto"), &site_info)
+                .to_string_lossy(),
+            "Module/Arguments.lua"
+        );
+        assert_eq!(
+            get_page_file_name("Module:Arguments/data", 828, Some("json"), &site_info)
+                .to_string_lossy(),
+            "Module/Arguments%2Fdata.json"
+        );
+        // Slots of pages stored as is don't lose the extension of the page
+        assert_eq!(
+            get_slot_file_name(Path::new("MediaWiki/Common.css"), "doc", Some("wikitext"))
+                .to_string_lossy(),
+            "MediaWiki/Common.css.doc.wiki"
+        );
+    }
+
+    #[test]
+    fn test_slot_files() {
+        let file_path = Path::new("File/foobar.png.md");
+        let slot_file_path = get_slot_file_name(file_path, "mediainfo", Some("wikibase-mediainfo"));
+        assert_eq!(
+            slot_file_path.to_string_lossy(),
+            "File/foobar.png.mediainfo.json"
+        );
+        assert_eq!(
+            get_slot_file_name(file_path, "module doc", Some("Scribunto")).to_string_lossy(),
+            "File/foobar.png.module_doc.lua"
+        );
+
+        let (mut repository, repository_path, committer) = create_test_repo("test_slot_files");
+        std::fs::create_dir_all(repository_path.join("File")).unwrap();
+        for path in [file_path, &slot_file_path, Path::new("File/foobar.png")] {
+            std::fs::write(repository_path.join(path), "Hello").unwrap();
+        }
+        create_commit_from_metadata(
+            &mut repository,
+            committer.clone(),
+            committer.clone(),
+            "master",
+            &[file_path, &slot_file_path, Path::new("File/foobar.png")],
+            &[],
+            "Add slot",
+        );
+        let slot_file_names = get_slot_file_names(&repository, "master", file_path).unwrap();
+        assert_eq!(
+            slot_file_names,
+            [Path::new("File/foobar.png.mediainfo.json")]
+        );
+
+        // Files that are gone from the working directory are removed
+        std::fs::remove_file(repository_path.join(&slot_file_path)).unwrap();
+        create_commit_from_metadata(
+            &mut repository,
+            committer.clone(),
+            committer.clone(),
+            "master",
+            &[&slot_file_path],
+            &[],
+            "Remove slot",
+        );
+        assert!(get_slot_file_names(&repository, "master", file_path)
+            .unwrap()
+            .is_empty());
+
+        // Null edits aren't committed
+        let head = get_most_recent_commit(&repository, "master").unwrap().id();
+        create_commit_from_metadata(
+            &mut repository,
+            committer.clone(),
+            committer,
+            "master",
+            &[file_path],
+            &[],
+            "Null edit",
+        );
+        assert_eq!(
+            get_most_recent_commit(&repository, "master").unwrap().id(),
+            head
+        );
+        std::fs::remove_dir_all(&repository_path).unwrap();
+    }
+
+    #[test]
+    fn test_move_files() {
+        let (mut repository, repository_path, committer) = create_test_repo("test_move_files");
+        let site_info = SiteInfo::default();
+        let file_path = get_file_name("Foo", 0, &site_info);
+        let slot_file_path = get_slot_file_name(&file_path, "mediainfo", Some("json"));
+        std::fs::create_dir_all(repository_path.join("Main")).unwrap();
+        for path in [&file_path, &slot_file_path] {
+            std::fs::write(repository_path.join(path), "Hello").unwrap();
+        }
+        create_commit_from_metadata(
+            &mut repository,
+            committer.clone(),
+            committer.clone(),
+            "master",
+            &[&file_path, &slot_file_path],
+            &[],
+            "Create\n\nWiki-Revision-Id: 10\nWiki-Page-Id: 1",
+        );
+        assert_eq!(
+            find_page_file(&repository, "master", "Foo", 0, &site_info).unwrap(),
+            Some((file_path.clone(), None))
+        );
+        assert_eq!(
+            get_file_page_id(&repository, "master", &file_path).unwrap(),
+            Some(1)
+        );
+
+        let page_move = ParsedMove {
+            logid: 7,
+            pageid: 1,
+            timestamp: time::macros::datetime!(2023-01-02 00:00:00),
+            user: crate::fetch_revisions::Contributor::User("Bar".into()),
+            comment: "Better name".into(),
+            old_namespace: 0,
+            old_title: "Foo".into(),
+            new_namespace: 0,
+            new_title: "Bar".into(),
+        };
+        let new_file_path = get_file_name("Bar", 0, &site_info);
+        let moved_file_names = get_moved_file_names(
+            &repository,
+            "master",
+            &file_path,
+            &new_file_path,
+            &page_move,
+            &site_info,
+        )
+        .unwrap();
+        assert_eq!(
+            moved_file_names,
+            [
+                (file_path.clone(), new_file_path.clone()),
+                (slot_file_path, PathBuf::from("Main/Bar.mediainfo.json"))
+            ]
+        );
+        create_move_commit(
+            &repository,
+            &committer,
+            &committer,
+            "master",
+            &moved_file_names,
+            &get_move_commit_message(&page_move),
+        )
+        .unwrap();
+        let commit = get_most_recent_commit(&repository, "master").unwrap();
+        assert_eq!(
+            commit.message().unwrap(),
+            "Moved page 'Foo' to 'Bar': Better name\n\nWiki-Log-Id: 7\nWiki-Page-Id: 1"
+        );
+        assert!(find_page_file(&repository, "master", "Foo", 0, &site_info)
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            get_file_page_id(&repository, "master", &new_file_path).unwrap(),
+            Some(1)
+        );
+        std::fs::remove_dir_all(&repository_path).unwrap();
+    }
+
+    #[test]
+    fn test_delete_and_restore_files() {
+        let (mut repository, repository_path, committer) =
+            create_test_repo("test_delete_and_restore_files");
+        let site_info = SiteInfo::default();
+        let file_path = get_file_name("Foo", 0, &site_info);
+        let slot_file_path = get_slot_file_name(&file_path, "mediainfo", Some("json"));
+        std::fs::create_dir_all(repository_path.join("Main")).unwrap();
+        for path in [&file_path, &slot_file_path] {
+            std::fs::write(repository_path.join(path), "Hello").unwrap();
+        }
+        create_commit_from_metadata(
+            &mut repository,
+            committer.clone(),
+            committer.clone(),
+            "master",
+            &[&file_path, &slot_file_path],
+            &[],
+            "Create\n\nWiki-Revision-Id: 10\nWiki-Page-Id: 1",
+        );
+
+        let mut deletion = ParsedDeletion {
+            logid: 9,
+            pageid: 1,
+            timestamp: time::macros::datetime!(2023-01-04 00:00:00),
+            user: crate::fetch_revisions::Contributor::User("Admin".into()),
+            comment: "Spam".into(),
+            namespace: 0,
+            title: "Foo".into(),
+            restored: false,
+        };
+        let tree = get_most_recent_commit(&repository, "master")
+            .unwrap()
+            .tree()
+            .unwrap();
+        let file_paths =
+            get_page_file_names(&repository, &tree, &file_path, "Foo", 0, &site_info).unwrap();
+        assert_eq!(file_paths, [file_path.clone(), slot_file_path.clone()]);
+        create_deletion_commit(
+            &repository,
+            &committer,
+            &committer,
+            "master",
+            &file_paths,
+            None,
+            &get_deletion_commit_message(&deletion),
+        )
+        .unwrap();
+        let commit = get_most_recent_commit(&repository, "master").unwrap();
+        assert_eq!(
+            commit.message().unwrap(),
+            "Spam\n\nWiki-Log-Id: 9\nWiki-Page-Id: 1"
+        );
+        assert_eq!(commit.tree().unwrap().len(), 0);
+
+        deletion.logid = 10;
+        deletion.comment = String::new();
+        deletion.restored = true;
+        let (tree, file_paths) = find_last_page_files(&repository, "master", "Foo", 0, &site_info)
+            .unwrap()
+            .unwrap();
+        create_deletion_commit(
+            &repository,
+            &committer,
+            &committer,
+            "master",
+            &file_paths,
+            Some(&tree),
+            &get_deletion_commit_message(&deletion),
+        )
+        .unwrap();
+        let commit = get_most_recent_commit(&repository, "master").unwrap();
+        assert_eq!(
+            commit.message().unwrap(),
+            "Restored page 'Foo'\n\nWiki-Log-Id: 10\nWiki-Page-Id: 1"
+        );
+        assert_eq!(commit.tree().unwrap().id(), tree.id());
+        assert_eq!(
+            std::fs::read_to_string(repository_path.join(&slot_file_path)).unwrap(),
+            "Hello"
+        );
+        std::fs::remove_dir_all(&repository_path).unwrap();
+    }
+
+    #[test]
+    fn test_get_relative_path() {
+        assert_eq!(
+            get_relative_path(Path::new("Main/Foo.md"), Path::new("Main/Bar.md")),
+            PathBuf::from("Bar.md")
+        );
+        assert_eq!(
+            get_relative_path(Path::new("Main/Foo.md"), Path::new("Help/Bar.md")),
+            PathBuf::from("../Help/Bar.md")
+        );
+    }
+
+    #[test]
+    fn test_commit_redirect_map() {
+        let (repository, repository_path, committer) = create_test_repo("test_commit_redirect_map");
+        assert!(read_redirect_map(&repository, "master").unwrap().is_empty());
+
+        let redirect_map = BTreeMap::from([("Main/Foo.md".into(), "Main/Bar.md#baz".into())]);
+        commit_redirect_map(&repository, &committer, "master", &redirect_map).unwrap();
+        let commit = get_most_recent_commit(&repository, "master").unwrap();
+        assert_eq!(commit.message(), Some("Update the map of redirects"));
+        assert_eq!(
+            read_redirect_map(&repository, "master").unwrap(),
+            redirect_map
+        );
+
+        // Nothing to commit if the map didn't change
+        commit_redirect_map(&repository, &committer, "master", &redirect_map).unwrap();
+        let last_commit = get_most_recent_commit(&repository, "master").unwrap();
+        assert_eq!(last_commit.id(), commit.id());
+        std::fs::remove_dir_all(&repository_path).unwrap();
+    }
+
     #[test]
     fn test_get_branch_name_usertalk_namespace() {
         assert_eq!(
-            get_branch_name("User Talk:Hello world.", 3),
+            get_branch_name("User Talk:Hello world.", 3, &SiteInfo::default()),
             "User%20Talk%3AHello%20world%2E".to_string()
         );
     }
+
+    fn german_site_info() -> SiteInfo {
+        let namespace = |key: i32, name: &str| NamespaceInfo {
+            key,
+            case: "first-letter".into(),
+            name: name.into(),
+        };
+        SiteInfo {
+            sitename: "Wikipedia".into(),
+            namespaces: NamespaceList {
+                namespaces: vec![
+                    namespace(0, ""),
+                    namespace(2, "Benutzer"),
+                    namespace(3, "Benutzer Diskussion"),
+                    namespace(6, "Datei"),
+                ],
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_get_file_name_localized_namespaces() {
+        let site_info = german_site_info();
+        assert_eq!(
+            get_file_name("Benutzer Diskussion:Foo: Bar", 3, &site_info).to_string_lossy(),
+            "Benutzer_Diskussion/Foo%3A_Bar.md"
+        );
+        // Gendered alias of the "Benutzer" namespace
+        assert_eq!(
+            get_file_name("Benutzerin:Foo", 2, &site_info).to_string_lossy(),
+            "Benutzer/Foo.md"
+        );
+        assert_eq!(
+            get_file_name("Datei:foobar.png", 6, &site_info).to_string_lossy(),
+            "Datei/foobar.png.md"
+        );
+        assert_eq!(
+            get_upload_file_name("Datei:foobar.png", &site_info).to_string_lossy(),
+            "Datei/foobar.png"
+        );
+        assert_eq!(
+            get_file_name("Hallo: Welt", 0, &site_info).to_string_lossy(),
+            "Main/Hallo%3A_Welt.md"
+        );
+    }
+
+    #[test]
+    fn test_get_branch_name_localized_namespaces() {
+        let site_info = german_site_info();
+        assert_eq!(
+            get_branch_name("Benutzerin:Foo", 2, &site_info),
+            "Benutzer%3AFoo".to_string()
+        );
+    }
 }
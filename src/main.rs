@@ -1,38 +1,78 @@
+mod api_client;
 mod convert_file;
+mod credentials;
+mod export_url_map;
+mod export_xml_dump;
 mod fetch_all_pages;
+mod fetch_category_members;
+mod fetch_latest_revisions;
+mod fetch_log_events;
+mod fetch_pages_by_title;
+mod fetch_recent_changes;
 mod fetch_revisions;
+mod fetch_site_info;
+mod find_api_url;
 mod get_author_data;
 mod handle_git;
 mod parse_xml_dump;
+mod select_pages;
 
-use clap::Parser;
+use api_client::{ApiClient, ApiError, ClientOptions};
+use clap::{Parser, ValueEnum};
 use git2::{BranchType, Repository, Signature, Time};
-use reqwest::Error;
-use time::OffsetDateTime;
-use tokio::{spawn, sync::mpsc};
-use tracing::{info, info_span, trace, warn, Instrument};
+use quick_xml::DeError;
+use regex::Regex;
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tokio::{spawn, sync::mpsc, task::spawn_blocking};
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
 use tracing_subscriber::EnvFilter;
 
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 
-use convert_file::convert_file;
+use convert_file::{get_anchor, get_redirect_target, is_wikitext, write_page, write_redirect_stub};
+use credentials::load_credentials;
+use export_url_map::export_url_map;
+use export_xml_dump::export_xml_dump;
 use fetch_all_pages::{fetch_all_pages, Page};
-use fetch_revisions::{fetch_revisions, get_parsed_revisions, ParsedRevision};
+use fetch_latest_revisions::{fetch_latest_revisions, get_parsed_latest_revisions};
+use fetch_log_events::{
+    fetch_log_events, get_parsed_deletions, get_parsed_moves, ParsedDeletion, ParsedLogEvent,
+    ParsedMove,
+};
+use fetch_recent_changes::fetch_last_change;
+use fetch_revisions::{fetch_revisions, get_parsed_revisions, Contributor, ParsedRevision};
+use fetch_site_info::{fetch_site_info, MediaWikiVersion};
+use find_api_url::find_api_url;
 use get_author_data::{load_author_data, Author, AuthorData};
 use handle_git::{
-    create_branch, create_commit_from_metadata, get_branch_name, get_file_name, get_signature,
-    rebase_branch,
+    commit_redirect_map, create_branch, create_commit_from_metadata, create_deletion_commit,
+    create_move_commit, find_last_page_files, find_page_file, get_branch_name, get_commit_message,
+    get_deletion_commit_message, get_file_name, get_file_page_id, get_imported_revisions,
+    get_imported_uploads, get_last_sync, get_move_commit_message, get_moved_file_names,
+    get_page_file_name, get_page_file_names, get_relative_path, get_signature, get_slot_file_name,
+    get_slot_file_names, get_upload_commit_message, get_upload_file_name,
+    open_repo_without_symlinks, read_redirect_map, rebase_branch, set_last_sync, FILE_NAMESPACE,
+};
+use parse_xml_dump::{
+    get_parsed_revision_from_dump, get_parsed_upload_from_dump, DumpReader, PageDump, ParsedUpload,
+    SiteInfo, UploadContents,
 };
+use select_pages::{fetch_changed_pages, fetch_selected_pages, load_titles, PageSelection};
 
 use crate::handle_git::get_most_recent_commit;
 
-// TODO - skip redirections
 // TODO - unwrap
 
 /// CLI utility to convert MediaWiki pages to Gitlab Markdown with git history
 #[derive(Debug, Parser)]
 struct ProgramArgs {
-    /// The base url of the wiki, e.g. https://wiki.archlinux.org
+    /// The url of the wiki, e.g. https://wiki.archlinux.org, the url of one of its pages or
+    /// of its api.php, or the path to an XML dump
+    /// produced by Special:Export or dumpBackup.php, optionally compressed with bzip2, gzip
+    /// or zstd
     wiki_url: String,
 
     /// The directory to store the git repository in
@@ -49,26 +89,177 @@ struct ProgramArgs {
     #[arg(short, long)]
     revision_count: Option<u32>,
 
-    /// A comma-separated list of namespaces to fetch. Default to 0 (main namespace)
+    /// Fetch the latest revision of many pages per request, and only fetch the full
+    /// history of pages that have more new revisions. Much faster on wikis with
+    /// many pages and few edits
+    #[arg(long)]
+    batched: bool,
+
+    /// Ask the wiki to reject our requests while its replicas are lagging by more than
+    /// this many seconds, in which case we wait and retry. 0 to disable
+    #[arg(long, default_value_t = 5)]
+    maxlag: u32,
+
+    /// A maximum number of requests per second to send to the wiki
+    #[arg(long)]
+    requests_per_second: Option<f64>,
+
+    /// How many times to retry a request after a server error or a timeout
+    #[arg(long, default_value_t = 5)]
+    max_retries: u32,
+
+    /// A JSON file with the `username` and `password` of a bot password, or an
+    /// `oauth_token`, to log in to wikis that require it to read. Default to the
+    /// CONVERT_WIKI_USERNAME and CONVERT_WIKI_PASSWORD, or CONVERT_WIKI_OAUTH_TOKEN,
+    /// environment variables
+    #[arg(long)]
+    credentials: Option<PathBuf>,
+
+    /// An XML dump of the same wiki to import first, optionally compressed. Only the
+    /// revisions more recent than the dump are then fetched through the API
+    #[arg(long)]
+    bootstrap_dump: Option<PathBuf>,
+
+    /// Import the files embedded in or referenced by dumps made with `--uploads`,
+    /// with one commit per version of each file
+    #[arg(long)]
+    uploads: bool,
+
+    /// Apply an adds-changes dump, like the daily `pages-meta-hist-incr` dumps of
+    /// Wikimedia, on top of a repository converted from an earlier dump
+    #[arg(long)]
+    incremental: bool,
+
+    /// Instead of converting the wiki, export the repository back into an XML dump
+    /// at this path. The wiki is only used to find the namespaces of pages
+    #[arg(long)]
+    export: Option<PathBuf>,
+
+    /// Instead of converting the wiki, write maps from the URLs of its pages to their
+    /// location in the repository for nginx, Apache and as JSON, to this directory.
+    /// The wiki is only used to find its URLs and the namespaces of pages
+    #[arg(long, conflicts_with = "export")]
+    url_map: Option<PathBuf>,
+
+    /// The URL the repository is rendered at, which the URL map redirects to
+    #[arg(long, default_value = "/", requires = "url_map")]
+    url_map_base: String,
+
+    /// A comma-separated list of namespaces to fetch. Default to 0 (main namespace),
+    /// or to every namespace when converting an XML dump
     #[arg(short, long)]
     namespaces: Option<String>,
+
+    /// Only import this page, given by its full title. Can be repeated
+    #[arg(long = "title", value_name = "TITLE")]
+    titles: Vec<String>,
+
+    /// A file listing the full titles of the pages to import, one per line
+    #[arg(long)]
+    titles_file: Option<PathBuf>,
+
+    /// Only import the members of this category. When pages are selected by title
+    /// or category, --namespaces defaults to the namespaces they are in
+    #[arg(long)]
+    category: Option<String>,
+
+    /// How many levels of subcategories of --category to import the members of too
+    #[arg(long, default_value_t = 0, requires = "category")]
+    category_depth: u32,
+
+    /// Also import the pages of the subcategories of --category, and not only
+    /// their members
+    #[arg(long, requires = "category")]
+    include_subcategories: bool,
+
+    /// Only import the pages whose title, without the namespace prefix, starts
+    /// with this prefix
+    #[arg(long, conflicts_with_all = ["titles", "titles_file", "category"])]
+    prefix: Option<String>,
+
+    /// Only import the pages whose full title matches this regular expression
+    #[arg(long)]
+    include: Option<Regex>,
+
+    /// Don't import the pages whose full title matches this regular expression
+    #[arg(long)]
+    exclude: Option<Regex>,
+
+    /// Only import the pages that were edited, created or moved since the last run
+    /// with --sync, found in the recent changes of the wiki, which only go back a
+    /// few weeks. The first run imports every page
+    #[arg(long, conflicts_with = "bootstrap_dump")]
+    sync: bool,
+
+    /// What to do with revisions whose content was hidden by an administrator. Their
+    /// hidden author or edit summary is replaced with a placeholder
+    #[arg(long, value_enum, default_value_t = HiddenText::KeepPrevious)]
+    hidden_text: HiddenText,
+
+    /// What to do with redirect pages
+    #[arg(long, value_enum, default_value_t = Redirects::Convert)]
+    redirects: Redirects,
+}
+
+/// How to import revisions whose content was hidden by revision deletion
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum HiddenText {
+    /// Don't import them
+    Skip,
+    /// Commit an empty page
+    Empty,
+    /// Commit the page unchanged, so that only the author and message are kept
+    KeepPrevious,
+}
+
+/// How to import the revisions that make a page a redirect
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Redirects {
+    /// Convert them like other pages
+    Convert,
+    /// Don't import the pages that are redirects, nor the revisions that made other
+    /// pages redirects
+    Skip,
+    /// Write a short Markdown file linking to the target
+    Stub,
+    /// Make the file a symbolic link to the file of the target. The output
+    /// directory holds the target instead, so run `git checkout -f` there to get the
+    /// links once done
+    Symlink,
+    /// Remove the file, and add the redirect to a map at the root of the repository
+    Map,
+}
+
+/// State shared by the dump and API conversions
+struct Conversion {
+    client: ApiClient,
+    author_data: AuthorData,
+    committer: Signature<'static>,
+    repository: Repository,
+    output_dir: PathBuf,
+    /// The id of the most recent imported revision of each page, by page id
+    imported_revisions: HashMap<u64, u64>,
+    /// The timestamp of the most recent imported upload of each file, by page id
+    imported_uploads: HashMap<u64, PrimitiveDateTime>,
+    hidden_text: HiddenText,
+    redirects: Redirects,
+    /// The map of redirects being built, for `Redirects::Map`
+    redirect_map: BTreeMap<String, String>,
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Error> {
+async fn main() -> Result<(), ApiError> {
     let program_args = ProgramArgs::parse();
-    let output_dir = program_args.output_dir.unwrap_or(PathBuf::from("output"));
+    let output_dir = program_args
+        .output_dir
+        .clone()
+        .unwrap_or(PathBuf::from("output"));
 
     // TODO - Add better tracing
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::try_from_default_env().unwrap_or(EnvFilter::new("info")))
         .init();
 
-    let url = if program_args.wiki_url.ends_with("/api.php") {
-        program_args.wiki_url
-    } else {
-        format!("{}/api.php", program_args.wiki_url)
-    };
     let author_data = if let Some(author_data_path) = program_args.author_data.as_ref() {
         load_author_data(author_data_path).unwrap()
     } else {
@@ -79,118 +270,920 @@ async fn main() -> Result<(), Error> {
     let committer =
         Signature::new("CONVERT_WIKI", "no-email@example.com", &Time::new(0, 0)).unwrap();
 
-    let client = reqwest::Client::new();
+    let client = ApiClient::new(ClientOptions {
+        maxlag: Some(program_args.maxlag).filter(|maxlag| *maxlag > 0),
+        requests_per_second: program_args.requests_per_second,
+        max_retries: program_args.max_retries,
+        credentials: load_credentials(program_args.credentials.as_deref()).unwrap(),
+        ..Default::default()
+    });
+
+    let dump_path = PathBuf::from(&program_args.wiki_url);
+    if program_args.export.is_some() || program_args.url_map.is_some() {
+        // Exports read an existing repository, rather than creating an empty one
+        let Ok(repository) = Repository::open(&output_dir) else {
+            error!(
+                "Exports are made from an existing repository, '{}' isn't one",
+                output_dir.to_string_lossy()
+            );
+            std::process::exit(1);
+        };
+        let site_info = if dump_path.is_file() {
+            let mut reader = DumpReader::open(&dump_path).unwrap();
+            reader.next_page().unwrap();
+            reader.take_site_info().unwrap_or_default()
+        } else {
+            let url = find_api_url(&client, &program_args.wiki_url).await?;
+            fetch_site_info(&client, &url).await?
+        };
+
+        if let Some(url_map_dir) = &program_args.url_map {
+            export_url_map(
+                &repository,
+                "master",
+                &site_info,
+                &program_args.url_map_base,
+                url_map_dir,
+            )
+            .unwrap();
+        }
+        if let Some(export_path) = &program_args.export {
+            info!("Exporting '{}'", export_path.to_string_lossy());
+            let mut output = BufWriter::new(File::create(export_path).unwrap());
+            export_xml_dump(&repository, "master", &site_info, &author_data, &mut output).unwrap();
+            output.flush().unwrap();
+        }
+        return Ok(());
+    }
 
     // If path exists, open repository, else create new repository
+    if program_args.incremental && !output_dir.exists() {
+        error!(
+            "Incremental dumps must be applied to an existing repository, '{}' doesn't exist",
+            output_dir.to_string_lossy()
+        );
+        std::process::exit(1);
+    }
     let mut repository = if output_dir.exists() {
         Repository::open(&output_dir).unwrap()
     } else {
-        handle_git::create_repo(&output_dir.to_str().unwrap(), &committer).unwrap()
+        handle_git::create_repo(output_dir.to_str().unwrap(), &committer).unwrap()
+    };
+    if let Redirects::Symlink = program_args.redirects {
+        repository = open_repo_without_symlinks(&output_dir).unwrap();
+    }
+    let imported_revisions = get_imported_revisions(&repository).unwrap();
+    let imported_uploads = if program_args.uploads {
+        get_imported_uploads(&repository).unwrap()
+    } else {
+        HashMap::new()
+    };
+
+    let redirect_map = match program_args.redirects {
+        Redirects::Map => read_redirect_map(&repository, "master").unwrap(),
+        _ => BTreeMap::new(),
+    };
+
+    let mut conversion = Conversion {
+        client,
+        author_data,
+        committer,
+        repository,
+        output_dir,
+        imported_revisions,
+        imported_uploads,
+        hidden_text: program_args.hidden_text,
+        redirects: program_args.redirects,
+        redirect_map,
     };
 
     // TODO - remove unwrap
-    let namespaces: Vec<u32> = program_args
+    let namespaces: Option<Vec<u32>> = program_args
         .namespaces
-        .unwrap_or("0".into())
-        .split(",")
-        .map(|s| s.parse().unwrap())
-        .collect();
+        .as_ref()
+        .map(|namespaces| namespaces.split(',').map(|s| s.parse().unwrap()).collect());
+
+    let dump_path = PathBuf::from(&program_args.wiki_url);
+    if dump_path.is_file() {
+        convert_dump(&mut conversion, dump_path, namespaces, &program_args).await;
+        return Ok(());
+    }
+
+    // Before the bootstrap dump, which can take a while
+    let url = find_api_url(&conversion.client, &program_args.wiki_url).await?;
+    let mut namespaces = namespaces;
+    if let Some(dump_path) = program_args.bootstrap_dump.clone() {
+        let dump_namespaces = convert_dump(
+            &mut conversion,
+            dump_path,
+            namespaces.clone(),
+            &program_args,
+        )
+        .await;
+        // Top up the same namespaces the dump covered
+        namespaces = namespaces.or(Some(dump_namespaces));
+    }
+
+    convert_api(&mut conversion, url, namespaces, &program_args).await
+}
+
+/// Gathers the pages selected on the command line, which only apply to the API
+fn get_page_selection(program_args: &ProgramArgs) -> PageSelection {
+    let mut titles = program_args.titles.clone();
+    if let Some(titles_file) = &program_args.titles_file {
+        titles.extend(load_titles(titles_file).unwrap());
+    }
+    PageSelection {
+        titles,
+        category: program_args.category.clone(),
+        category_depth: program_args.category_depth,
+        include_subcategories: program_args.include_subcategories,
+        prefix: program_args.prefix.clone(),
+        include: program_args.include.clone(),
+        exclude: program_args.exclude.clone(),
+        skip_redirects: matches!(program_args.redirects, Redirects::Skip),
+    }
+}
+
+/// Commits the map of redirects to master, for `Redirects::Map`
+fn commit_conversion_redirect_map(conversion: &Conversion) {
+    if let Redirects::Map = conversion.redirects {
+        commit_redirect_map(
+            &conversion.repository,
+            &conversion.committer,
+            "master",
+            &conversion.redirect_map,
+        )
+        .unwrap();
+    }
+}
+
+/// Imports the pages of an XML dump, and returns the namespaces they were in.
+async fn convert_dump(
+    conversion: &mut Conversion,
+    dump_path: PathBuf,
+    namespaces: Option<Vec<u32>>,
+    program_args: &ProgramArgs,
+) -> Vec<u32> {
+    let (mut dump_sender, mut dump_receiver) = mpsc::channel(32);
+    // Only used to download uploads that aren't embedded in the dump
+    let client = conversion.client.clone();
+
+    // The XML parser is synchronous, so it runs on its own thread
+    let page_count = program_args.page_count;
+    let revision_count = program_args.revision_count;
+    let uploads = program_args.uploads;
+    let skip_redirects = matches!(program_args.redirects, Redirects::Skip);
+    let dump_task = spawn_blocking(move || {
+        let _span = info_span!("task_read_dump").entered();
+        task_read_dump(
+            &dump_path,
+            &mut dump_sender,
+            namespaces,
+            page_count,
+            revision_count,
+            uploads,
+            skip_redirects,
+        )
+    });
+
+    let mut site_info = SiteInfo::default();
+    let mut dump_namespaces = Vec::new();
+    let mut namespace = 0;
+    let mut branch_name = String::new();
+    let mut resume_point = ResumePoint::Start;
+    let mut count = 0;
+    while let Some(item) = dump_receiver.recv().await {
+        match item {
+            DumpItem::SiteInfo(dump_site_info) => {
+                info!("Converting dump of '{}'", dump_site_info.sitename);
+                site_info = dump_site_info;
+            }
+            DumpItem::Page(page) => {
+                info!("Importing revisions for page '{}'", page.title);
+                namespace = page.ns;
+                if !dump_namespaces.contains(&namespace) {
+                    dump_namespaces.push(namespace);
+                }
+                branch_name = get_branch_name(&page.title, namespace, &site_info);
+                resume_point = prepare_branch(conversion, &branch_name, page.id);
+                count = 0;
+            }
+            DumpItem::Revision(revision) => {
+                // The dump is read in full on each run, so we skip the revisions
+                // that were already committed by a previous one.
+                if resume_point.is_imported(&revision) {
+                    trace!("Skipping already imported revision {}", revision.revid);
+                    continue;
+                }
+                if count == 0 {
+                    check_history_gap(resume_point, &revision, program_args.incremental);
+                }
+
+                let span = info_span!("task_process_revision", revision = revision.revid);
+                task_process_revision(conversion, &site_info, revision, namespace)
+                    .instrument(span)
+                    .await
+                    .unwrap();
+                count += 1;
+            }
+            DumpItem::Upload(upload) => {
+                let last_upload = conversion.imported_uploads.get(&upload.pageid);
+                if last_upload.is_some_and(|last_upload| upload.timestamp <= *last_upload) {
+                    trace!("Skipping already imported upload of {}", upload.timestamp);
+                    continue;
+                }
+
+                let title = upload.title.clone();
+                let span = info_span!("task_process_upload", page = title.clone());
+                let result = task_process_upload(conversion, &site_info, &client, upload)
+                    .instrument(span)
+                    .await;
+                if let Err(error) = result {
+                    error!(
+                        "Failed to import an upload of '{}', skipping: {}",
+                        title, error
+                    );
+                }
+            }
+            DumpItem::PageEnd => {
+                info!("Imported {} revisions", count);
+                rebase_branch(
+                    &conversion.repository,
+                    &branch_name,
+                    &conversion.committer,
+                    "master",
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    dump_task.await.unwrap().unwrap();
+    commit_conversion_redirect_map(conversion);
+    dump_namespaces
+}
+
+/// Imports the selected pages of the given namespaces through the MediaWiki API.
+async fn convert_api(
+    conversion: &mut Conversion,
+    url: String,
+    namespaces: Option<Vec<u32>>,
+    program_args: &ProgramArgs,
+) -> Result<(), ApiError> {
+    let page_count = program_args.page_count;
+    let revision_count = program_args.revision_count;
+    let batched = program_args.batched;
+    let client = conversion.client.clone();
+    let site_info = fetch_site_info(&client, &url).await?;
+    let version = MediaWikiVersion::from_generator(&site_info.generator);
+    debug!("Wiki runs {:?}", version);
+
+    // Pages given by title or category are found up front, whatever their namespace
+    let selection = get_page_selection(program_args);
+    let mut selected_pages = if selection.is_listed() {
+        Some(fetch_selected_pages(&client, &url, &selection, &site_info, &version).await?)
+    } else {
+        None
+    };
+    let namespaces = namespaces.unwrap_or_else(|| match &selected_pages {
+        Some(selected_pages) => selected_pages.keys().copied().collect(),
+        None => vec![0],
+    });
+
+    // Syncs only import the pages that changed since the most recent change the
+    // last one saw, and record the most recent change they see for the next one
+    let last_sync = if program_args.sync {
+        get_last_sync(&conversion.repository)
+    } else {
+        None
+    };
+    let mut sync_point = None;
+    if program_args.sync {
+        if let Some(last_sync) = last_sync {
+            info!("Fetching the changes since {}", last_sync);
+            let (changed_pages, last_change) =
+                fetch_changed_pages(&client, &url, last_sync, &namespaces, &version).await?;
+            selected_pages =
+                Some(selection.filter_changed_pages(changed_pages, selected_pages.as_ref()));
+            sync_point = Some(last_change.unwrap_or(last_sync));
+        } else {
+            info!("First sync, importing every page");
+            sync_point = fetch_last_change(&client, &url, &namespaces, &version).await?;
+        }
+    }
+
+    // Pages moved, deleted or undeleted since the repository was last updated are
+    // handled first, each after the revisions made before it, so that their history
+    // carries on under their new title
+    let last_update = if conversion.imported_revisions.is_empty() {
+        None
+    } else {
+        let last_commit = get_most_recent_commit(&conversion.repository, "master").unwrap();
+        let seconds = last_commit.author().when().seconds();
+        Some(OffsetDateTime::from_unix_timestamp(seconds).unwrap())
+    };
+    if let Some(logs_since) = last_sync.or(last_update) {
+        let span = info_span!("task_process_log_events");
+        task_process_log_events(conversion, &site_info, &url, logs_since, version)
+            .instrument(span)
+            .await?;
+    }
+
+    let mut is_complete = true;
     for namespace in namespaces {
         let (mut page_sender, mut page_receiver) = mpsc::channel(8);
 
         // Set of thread-local tasks (which, given Repository is not Send, is everything)
         let client_clone = client.clone();
         let url_clone = url.clone();
+        let pages = selected_pages
+            .as_mut()
+            .map(|selected_pages| selected_pages.remove(&namespace).unwrap_or_default());
+        let selection = selection.clone();
         let pages_task = spawn(async move {
-            let span = info_span!("task_get_pages", url = url_clone);
-            task_get_pages(
-                &client_clone,
-                &url_clone,
-                &mut page_sender,
-                program_args.page_count,
-                namespace,
-            )
-            .instrument(span)
-            .await
+            if let Some(pages) = pages {
+                task_send_pages(&mut page_sender, pages, page_count).await
+            } else if batched {
+                let span = info_span!("task_get_latest_revisions", url = url_clone);
+                task_get_latest_revisions(
+                    &client_clone,
+                    &url_clone,
+                    &mut page_sender,
+                    page_count,
+                    namespace,
+                    &selection,
+                    version,
+                )
+                .instrument(span)
+                .await
+            } else {
+                let span = info_span!("task_get_pages", url = url_clone);
+                task_get_pages(
+                    &client_clone,
+                    &url_clone,
+                    &mut page_sender,
+                    page_count,
+                    namespace,
+                    &selection,
+                    version,
+                )
+                .instrument(span)
+                .await
+            }
         });
 
-        while let Some(page) = page_receiver.recv().await {
-            let branch_name = get_branch_name(&page.title, namespace);
-            let branch = repository.find_branch(&branch_name, BranchType::Local);
-            let last_commit_date;
-            if branch.is_err() {
-                // add new branch to repository if doesn't exist
-                create_branch(&repository, "base", &branch_name);
-                last_commit_date = None;
-            } else {
-                let last_commit = get_most_recent_commit(&repository, &branch_name).unwrap();
-                last_commit.author().when();
-                let datetime =
-                    OffsetDateTime::from_unix_timestamp(last_commit.author().when().seconds())
+        while let Some((page, latest_revision)) = page_receiver.recv().await {
+            let branch_name = get_branch_name(&page.title, namespace, &site_info);
+            let resume_point = prepare_branch(conversion, &branch_name, page.pageid);
+
+            if let Some(latest_revision) = latest_revision {
+                if resume_point.is_imported(&latest_revision) {
+                    trace!("Page '{}' is up to date", page.title);
+                    continue;
+                }
+                // No need to fetch the history if the latest revision is the only new one
+                let is_only_new_revision = match (resume_point, latest_revision.parentid) {
+                    (ResumePoint::Start, Some(0)) => true,
+                    (ResumePoint::Revision(revid), Some(parentid)) => parentid == revid,
+                    _ => false,
+                };
+                if is_only_new_revision {
+                    let span =
+                        info_span!("task_process_revision", revision = latest_revision.revid);
+                    task_process_revision(conversion, &site_info, latest_revision, namespace)
+                        .instrument(span)
+                        .await
                         .unwrap();
-                last_commit_date = Some(datetime);
+                    rebase_branch(
+                        &conversion.repository,
+                        &branch_name,
+                        &conversion.committer,
+                        "master",
+                    )
+                    .unwrap();
+                    continue;
+                }
+                trace!("Page '{}' has several new revisions", page.title);
             }
-            std::mem::drop(branch);
 
             let client_clone = client.clone();
             let url_clone = url.clone();
             let (mut rev_sender, mut rev_receiver) = mpsc::channel(32);
             let revs_task = spawn(async move {
-                let span = info_span!("task_get_revisions", page = page.title.clone());
-                let count = task_get_revisions(
+                let title = page.title.clone();
+                let span = info_span!("task_get_revisions", page = title);
+                let result = task_get_revisions(
                     &client_clone,
                     &url_clone,
                     page,
                     &mut rev_sender,
-                    last_commit_date,
-                    program_args.revision_count,
+                    resume_point,
+                    revision_count,
+                    version,
                 )
                 .instrument(span)
-                .await
-                .unwrap();
-                info!("Fetched {} revisions", count);
+                .await;
+                match result {
+                    Ok(count) => {
+                        info!("Fetched {} revisions", count);
+                        true
+                    }
+                    // The revisions fetched so far are kept, the next run resumes after them
+                    Err(error) => {
+                        error!("Failed to fetch the history of page '{}': {}", title, error);
+                        false
+                    }
+                }
             });
 
+            let mut count = 0;
             while let Some(revision) = rev_receiver.recv().await {
+                if count == 0 {
+                    check_history_gap(resume_point, &revision, false);
+                }
+                count += 1;
                 let span = info_span!("task_process_revision", revision = revision.revid);
-                task_process_revision(
-                    &author_data,
-                    revision,
-                    &mut repository,
-                    &output_dir,
-                    namespace,
-                )
+                task_process_revision(conversion, &site_info, revision, namespace)
+                    .instrument(span)
+                    .await
+                    .unwrap();
+            }
+
+            rebase_branch(
+                &conversion.repository,
+                &branch_name,
+                &conversion.committer,
+                "master",
+            )
+            .unwrap();
+
+            is_complete &= revs_task.await.unwrap();
+        }
+
+        pages_task.await.unwrap()?;
+    }
+
+    commit_conversion_redirect_map(conversion);
+
+    if let Some(sync_point) = sync_point {
+        if is_complete {
+            set_last_sync(&conversion.repository, sync_point).unwrap();
+        } else {
+            // Otherwise, the pages that failed wouldn't be part of the next sync
+            warn!("Some pages failed to import, the next sync starts from the same point");
+        }
+    }
+
+    Ok(())
+}
+
+/// Mirrors the page moves since the given date as renames of the files of the
+/// pages that were imported under their old title, and the deletions and
+/// undeletions as removals and restorations of their files, in the order they
+/// happened. The revisions of a page made before each move or deletion are
+/// committed first, the others are left to the import of the page.
+async fn task_process_log_events(
+    conversion: &mut Conversion,
+    site_info: &SiteInfo,
+    url: &str,
+    since: OffsetDateTime,
+    version: MediaWikiVersion,
+) -> Result<(), ApiError> {
+    info!("Fetching page moves and deletions");
+
+    let client = conversion.client.clone();
+    let mut log_events = Vec::new();
+    for log_type in ["move", "delete"] {
+        let mut continue_token = None;
+        loop {
+            let resp = fetch_log_events(
+                &client,
+                url,
+                log_type,
+                since,
+                None,
+                continue_token,
+                &version,
+            )
+            .await?;
+            if log_type == "move" {
+                let moves = get_parsed_moves(resp.query);
+                log_events.extend(moves.into_iter().map(ParsedLogEvent::Move));
+            } else {
+                let deletions = get_parsed_deletions(resp.query);
+                log_events.extend(deletions.into_iter().map(ParsedLogEvent::Deletion));
+            }
+
+            continue_token = resp.cont;
+            if continue_token.is_none() {
+                break;
+            }
+        }
+    }
+
+    log_events.sort_by_key(ParsedLogEvent::get_order);
+    for log_event in log_events {
+        let page = match &log_event {
+            ParsedLogEvent::Move(page_move) => Some((
+                page_move.pageid,
+                &page_move.old_title,
+                page_move.old_namespace,
+            )),
+            // Restored pages have no revisions since their deletion
+            ParsedLogEvent::Deletion(deletion) if !deletion.restored && deletion.pageid != 0 => {
+                Some((deletion.pageid, &deletion.title, deletion.namespace))
+            }
+            ParsedLogEvent::Deletion(_) => None,
+        };
+        if let Some((pageid, title, namespace)) = page {
+            let page = Page {
+                pageid,
+                title: title.clone(),
+            };
+            let (until, _) = log_event.get_order();
+            let result = import_revisions_before(
+                conversion, site_info, url, page, namespace, until, version,
+            )
+            .await;
+            // The change is still applied, the revisions are then imported after it
+            if let Err(error) = result {
+                error!(
+                    "Failed to fetch the revisions of page '{}' made before {}: {}",
+                    title, until, error
+                );
+            }
+        }
+
+        match log_event {
+            ParsedLogEvent::Move(page_move) => process_move(conversion, site_info, page_move),
+            ParsedLogEvent::Deletion(deletion) => process_deletion(conversion, site_info, deletion),
+        }
+    }
+    Ok(())
+}
+
+/// Commits the revisions of an imported page made before the given date, under the
+/// title it had then.
+async fn import_revisions_before(
+    conversion: &mut Conversion,
+    site_info: &SiteInfo,
+    url: &str,
+    page: Page,
+    namespace: u32,
+    until: PrimitiveDateTime,
+    version: MediaWikiVersion,
+) -> Result<(), ApiError> {
+    let branch_name = get_branch_name(&page.title, namespace, site_info);
+    if conversion
+        .repository
+        .find_branch(&branch_name, BranchType::Local)
+        .is_err()
+    {
+        return Ok(());
+    }
+    let resume_point = prepare_branch(conversion, &branch_name, page.pageid);
+    let (starting_date, starting_revid) = match resume_point {
+        ResumePoint::Start => (Some(OffsetDateTime::UNIX_EPOCH), None),
+        ResumePoint::Revision(revid) => (None, Some(revid)),
+        ResumePoint::Date(date) => (Some(date), None),
+    };
+
+    let client = conversion.client.clone();
+    let mut rv_continue_token = None;
+    let mut count = 0;
+    'fetch: loop {
+        let revisions = fetch_revisions(
+            &client,
+            url,
+            page.pageid,
+            Some(30),
+            starting_date,
+            starting_revid,
+            rv_continue_token,
+            &version,
+        )
+        .await?;
+
+        for revision in get_parsed_revisions(revisions.query, page.title.clone()) {
+            if resume_point.is_imported(&revision) {
+                continue;
+            }
+            if revision.timestamp >= until {
+                break 'fetch;
+            }
+            if count == 0 {
+                check_history_gap(resume_point, &revision, false);
+            }
+            count += 1;
+            let span = info_span!("task_process_revision", revision = revision.revid);
+            task_process_revision(conversion, site_info, revision, namespace)
                 .instrument(span)
                 .await
                 .unwrap();
+        }
+
+        rv_continue_token = revisions.cont;
+        if rv_continue_token.is_none() {
+            break;
+        }
+    }
+
+    if count > 0 {
+        info!(
+            "Imported {} revisions of page '{}' made before {}",
+            count, page.title, until
+        );
+        rebase_branch(
+            &conversion.repository,
+            &branch_name,
+            &conversion.committer,
+            "master",
+        )
+        .unwrap();
+    }
+    Ok(())
+}
+
+fn process_move(conversion: &mut Conversion, site_info: &SiteInfo, page_move: ParsedMove) {
+    let repository = &conversion.repository;
+    let branch_name = get_branch_name(&page_move.old_title, page_move.old_namespace, site_info);
+    if repository
+        .find_branch(&branch_name, BranchType::Local)
+        .is_err()
+    {
+        trace!("Page '{}' wasn't imported", page_move.old_title);
+        return;
+    }
+    let Some((file_path, model)) = find_page_file(
+        repository,
+        &branch_name,
+        &page_move.old_title,
+        page_move.old_namespace,
+        site_info,
+    )
+    .unwrap() else {
+        // With `Redirects::Map`, redirects only have an entry in the map
+        let file_path = get_file_name(&page_move.old_title, page_move.old_namespace, site_info);
+        match conversion
+            .redirect_map
+            .remove(file_path.to_string_lossy().as_ref())
+        {
+            Some(target) => {
+                let new_file_path =
+                    get_file_name(&page_move.new_title, page_move.new_namespace, site_info);
+                let new_file_path = new_file_path.to_string_lossy().into_owned();
+                conversion.redirect_map.insert(new_file_path, target);
+            }
+            None => trace!("Page '{}' has no file", page_move.old_title),
+        }
+        return;
+    };
+    // The old title may belong to another page since, e.g. the redirect left behind
+    let pageid = get_file_page_id(repository, &branch_name, &file_path).unwrap();
+    if pageid.is_some_and(|pageid| pageid != page_move.pageid) {
+        trace!("Page '{}' was already moved", page_move.old_title);
+        return;
+    }
+
+    info!(
+        "Moving page '{}' to '{}'",
+        page_move.old_title, page_move.new_title
+    );
+    let new_file_path = get_page_file_name(
+        &page_move.new_title,
+        page_move.new_namespace,
+        model,
+        site_info,
+    );
+    let moved_file_names = get_moved_file_names(
+        repository,
+        &branch_name,
+        &file_path,
+        &new_file_path,
+        &page_move,
+        site_info,
+    )
+    .unwrap();
+
+    // The history of the page carries on in the branch of its new title
+    let new_branch_name = get_branch_name(&page_move.new_title, page_move.new_namespace, site_info);
+    repository
+        .find_branch(&branch_name, BranchType::Local)
+        .unwrap()
+        .rename(&new_branch_name, true)
+        .unwrap();
+
+    let author_git_data = get_author_git_data(&conversion.author_data, &page_move.user);
+    let author = get_signature(page_move.timestamp, &author_git_data);
+    create_move_commit(
+        repository,
+        &conversion.committer,
+        &author,
+        &new_branch_name,
+        &moved_file_names,
+        &get_move_commit_message(&page_move),
+    )
+    .unwrap();
+    rebase_branch(
+        repository,
+        &new_branch_name,
+        &conversion.committer,
+        "master",
+    )
+    .unwrap();
+}
+
+fn process_deletion(
+    conversion: &mut Conversion,
+    site_info: &SiteInfo,
+    mut deletion: ParsedDeletion,
+) {
+    let repository = &conversion.repository;
+    let branch_name = get_branch_name(&deletion.title, deletion.namespace, site_info);
+    if repository
+        .find_branch(&branch_name, BranchType::Local)
+        .is_err()
+    {
+        trace!("Page '{}' wasn't imported", deletion.title);
+        return;
+    }
+    let page_file = find_page_file(
+        repository,
+        &branch_name,
+        &deletion.title,
+        deletion.namespace,
+        site_info,
+    )
+    .unwrap();
+
+    let (file_paths, restored_tree) = if deletion.restored {
+        if page_file.is_some() {
+            trace!("Page '{}' already exists", deletion.title);
+            return;
+        }
+        let Some((tree, file_paths)) = find_last_page_files(
+            repository,
+            &branch_name,
+            &deletion.title,
+            deletion.namespace,
+            site_info,
+        )
+        .unwrap() else {
+            trace!("Page '{}' has no file to restore", deletion.title);
+            return;
+        };
+        info!("Restoring page '{}'", deletion.title);
+        (file_paths, Some(tree))
+    } else {
+        let Some((file_path, _)) = page_file else {
+            // With `Redirects::Map`, redirects only have an entry in the map
+            let file_path = get_file_name(&deletion.title, deletion.namespace, site_info);
+            match conversion
+                .redirect_map
+                .remove(file_path.to_string_lossy().as_ref())
+            {
+                Some(_) => info!("Deleting redirect '{}'", deletion.title),
+                None => trace!("Page '{}' has no file", deletion.title),
+            }
+            return;
+        };
+        // The title may belong to another page since, e.g. if the page was moved
+        let pageid = get_file_page_id(repository, &branch_name, &file_path).unwrap();
+        match pageid {
+            Some(pageid) if deletion.pageid == 0 => deletion.pageid = pageid,
+            Some(pageid) if pageid != deletion.pageid => {
+                trace!("Page '{}' was already deleted", deletion.title);
+                return;
             }
+            _ => {}
+        }
+        info!("Deleting page '{}'", deletion.title);
+        let tree = get_most_recent_commit(repository, &branch_name)
+            .unwrap()
+            .tree()
+            .unwrap();
+        let file_paths = get_page_file_names(
+            repository,
+            &tree,
+            &file_path,
+            &deletion.title,
+            deletion.namespace,
+            site_info,
+        )
+        .unwrap();
+        (file_paths, None)
+    };
 
-            rebase_branch(&repository, &branch_name, &committer, "master").unwrap();
+    let author_git_data = get_author_git_data(&conversion.author_data, &deletion.user);
+    let author = get_signature(deletion.timestamp, &author_git_data);
+    create_deletion_commit(
+        repository,
+        &conversion.committer,
+        &author,
+        &branch_name,
+        &file_paths,
+        restored_tree.as_ref(),
+        &get_deletion_commit_message(&deletion),
+    )
+    .unwrap();
+    rebase_branch(repository, &branch_name, &conversion.committer, "master").unwrap();
+}
 
-            revs_task.await.unwrap();
+/// Where the conversion of a page resumes from
+#[derive(Clone, Copy, Debug)]
+enum ResumePoint {
+    /// Nothing was imported for this page yet
+    Start,
+    /// After the revision with this id
+    Revision(u64),
+    /// After this date, for branches whose commits don't record revision ids
+    Date(OffsetDateTime),
+}
+
+impl ResumePoint {
+    fn is_imported(&self, revision: &ParsedRevision) -> bool {
+        match self {
+            ResumePoint::Start => false,
+            ResumePoint::Revision(revid) => revision.revid <= *revid,
+            ResumePoint::Date(date) => revision.timestamp.assume_utc() <= *date,
         }
+    }
+}
 
-        pages_task.await.unwrap().unwrap();
+/// Warns if the first new revision of a page doesn't follow the last imported one,
+/// which happens when an incremental dump was skipped.
+fn check_history_gap(resume_point: ResumePoint, revision: &ParsedRevision, incremental: bool) {
+    match (resume_point, revision.parentid) {
+        (ResumePoint::Revision(revid), Some(parentid)) if parentid != revid => {
+            warn!(
+                "Revision {} of page '{}' follows revision {}, but the last imported one is {}; \
+                revisions in between are missing",
+                revision.revid, revision.title, parentid, revid
+            );
+        }
+        // Full dumps can start anywhere in the history of a page, but incremental
+        // ones should only have new pages.
+        (ResumePoint::Start, Some(parentid)) if incremental && parentid != 0 => {
+            warn!(
+                "Page '{}' isn't in the repository, its history before revision {} is missing",
+                revision.title, revision.revid
+            );
+        }
+        _ => {}
     }
+}
 
-    Ok(())
+/// Creates the branch of a page if it doesn't exist yet, and returns the point
+/// its conversion should resume from otherwise.
+fn prepare_branch(conversion: &Conversion, branch_name: &str, pageid: u64) -> ResumePoint {
+    let repository = &conversion.repository;
+    let branch = repository.find_branch(branch_name, BranchType::Local);
+    if branch.is_err() {
+        // add new branch to repository if doesn't exist
+        create_branch(repository, "base", branch_name);
+        ResumePoint::Start
+    } else if let Some(revid) = conversion.imported_revisions.get(&pageid) {
+        ResumePoint::Revision(*revid)
+    } else {
+        let last_commit = get_most_recent_commit(repository, branch_name).unwrap();
+        let datetime =
+            OffsetDateTime::from_unix_timestamp(last_commit.author().when().seconds()).unwrap();
+        ResumePoint::Date(datetime)
+    }
 }
 
 async fn task_get_pages(
-    client: &reqwest::Client,
+    client: &ApiClient,
     url: &str,
-    sender: &mut mpsc::Sender<Page>,
+    sender: &mut mpsc::Sender<(Page, Option<ParsedRevision>)>,
     page_count: Option<u32>,
     namespace: u32,
-) -> Result<(), Error> {
+    selection: &PageSelection,
+    version: MediaWikiVersion,
+) -> Result<(), ApiError> {
     info!("Fetching pages");
 
     let mut page_count = page_count;
     let mut ap_continue_token = None;
     loop {
-        let pages = fetch_all_pages(&client, url, Some(30), ap_continue_token, namespace).await?;
+        let pages = fetch_all_pages(
+            client,
+            url,
+            Some(30),
+            ap_continue_token,
+            namespace,
+            selection.prefix.as_deref(),
+            selection.skip_redirects,
+            &version,
+        )
+        .await?;
 
         for page in pages.query.allpages {
+            if !selection.matches(&page.title) {
+                trace!("Skipping page '{}'", page.title);
+                continue;
+            }
             if let Some(0) = page_count {
                 trace!("Reached page count limit, stopping");
                 return Ok(());
@@ -198,7 +1191,7 @@ async fn task_get_pages(
             page_count = page_count.map(|count| count - 1);
 
             info!("Fetched page {} '{}'", page.pageid, page.title);
-            sender.send(page).await.unwrap();
+            sender.send((page, None)).await.unwrap();
         }
 
         ap_continue_token = pages.cont;
@@ -209,42 +1202,128 @@ async fn task_get_pages(
     Ok(())
 }
 
+/// Like `task_get_pages`, but also sends the latest revision of each page.
+async fn task_get_latest_revisions(
+    client: &ApiClient,
+    url: &str,
+    sender: &mut mpsc::Sender<(Page, Option<ParsedRevision>)>,
+    page_count: Option<u32>,
+    namespace: u32,
+    selection: &PageSelection,
+    version: MediaWikiVersion,
+) -> Result<(), ApiError> {
+    info!("Fetching latest revisions");
+
+    let mut page_count = page_count;
+    let mut continue_token = None;
+    loop {
+        let resp = fetch_latest_revisions(
+            client,
+            url,
+            Some(50),
+            continue_token,
+            namespace,
+            selection.prefix.as_deref(),
+            selection.skip_redirects,
+            &version,
+        )
+        .await?;
+
+        let revisions = resp.query.map(get_parsed_latest_revisions);
+        for revision in revisions.into_iter().flatten() {
+            if !selection.matches(&revision.title) {
+                trace!("Skipping page '{}'", revision.title);
+                continue;
+            }
+            if let Some(0) = page_count {
+                trace!("Reached page count limit, stopping");
+                return Ok(());
+            }
+            page_count = page_count.map(|count| count - 1);
+
+            info!(
+                "Fetched revision {} of page {} '{}'",
+                revision.revid, revision.pageid, revision.title
+            );
+            let page = Page {
+                pageid: revision.pageid,
+                title: revision.title.clone(),
+            };
+            sender.send((page, Some(revision))).await.unwrap();
+        }
+
+        continue_token = resp.cont;
+        if continue_token.is_none() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Like `task_get_pages`, for pages that were already found by title or category.
+async fn task_send_pages(
+    sender: &mut mpsc::Sender<(Page, Option<ParsedRevision>)>,
+    pages: Vec<Page>,
+    page_count: Option<u32>,
+) -> Result<(), ApiError> {
+    let page_count = page_count.map_or(pages.len(), |count| count as usize);
+    for page in pages.into_iter().take(page_count) {
+        info!("Selected page {} '{}'", page.pageid, page.title);
+        sender.send((page, None)).await.unwrap();
+    }
+    Ok(())
+}
+
 async fn task_get_revisions(
-    client: &reqwest::Client,
+    client: &ApiClient,
     url: &str,
     page: Page,
     sender: &mut mpsc::Sender<ParsedRevision>,
-    starting_date: Option<OffsetDateTime>,
+    resume_point: ResumePoint,
     revision_count: Option<u32>,
-) -> Result<i32, Error> {
+    version: MediaWikiVersion,
+) -> Result<i32, ApiError> {
     let pageid = page.pageid;
     let mut revision_count = revision_count;
     let mut rv_continue_token = None;
     let mut count = 0;
 
-    if let Some(starting_date) = starting_date {
+    let (starting_date, starting_revid) = match resume_point {
+        // Without a start, the API returns the most recent revisions first
+        ResumePoint::Start => (Some(OffsetDateTime::UNIX_EPOCH), None),
+        ResumePoint::Revision(revid) => (None, Some(revid)),
+        ResumePoint::Date(date) => (Some(date), None),
+    };
+    if let ResumePoint::Start = resume_point {
+        info!("Fetching revisions for page '{}'", page.title);
+    } else {
         info!(
-            "Fetching revisions for page '{}' starting from {}",
-            page.title, starting_date
+            "Fetching revisions for page '{}' after {:?}",
+            page.title, resume_point
         );
-    } else {
-        info!("Fetching revisions for page '{}'", page.title);
     }
 
     loop {
         trace!("Fetching more revisions for page '{}'", page.title);
 
         let revisions = fetch_revisions(
-            &client,
+            client,
             url,
             pageid,
             Some(30),
             starting_date,
+            starting_revid,
             rv_continue_token,
+            &version,
         )
         .await?;
 
-        for revision in get_parsed_revisions(revisions.query, page.title.clone().into()) {
+        for revision in get_parsed_revisions(revisions.query, page.title.clone()) {
+            // The starting revision or date is included in the response
+            if resume_point.is_imported(&revision) {
+                trace!("Skipping already imported revision {}", revision.revid);
+                continue;
+            }
             if let Some(0) = revision_count {
                 trace!("Reached revision count limit, stopping");
                 return Ok(count);
@@ -268,23 +1347,180 @@ async fn task_get_revisions(
     Ok(count)
 }
 
+/// Items sent by `task_read_dump`, in document order.
+enum DumpItem {
+    /// Sent before the first page, if the dump has a `<siteinfo>` block
+    SiteInfo(SiteInfo),
+    /// Start of a page; its revisions follow
+    Page(PageDump),
+    Revision(ParsedRevision),
+    /// Sent after the revisions of a file page, oldest version first
+    Upload(ParsedUpload),
+    PageEnd,
+}
+
+fn task_read_dump(
+    path: &Path,
+    sender: &mut mpsc::Sender<DumpItem>,
+    namespaces: Option<Vec<u32>>,
+    page_count: Option<u32>,
+    revision_count: Option<u32>,
+    uploads: bool,
+    skip_redirects: bool,
+) -> Result<(), DeError> {
+    info!("Reading XML dump '{}'", path.to_string_lossy());
+
+    let mut reader = DumpReader::open(path).unwrap();
+    let mut page_count = page_count;
+    while let Some(page) = reader.next_page()? {
+        if let Some(site_info) = reader.take_site_info() {
+            sender.blocking_send(DumpItem::SiteInfo(site_info)).unwrap();
+        }
+        if let Some(namespaces) = &namespaces {
+            if !namespaces.contains(&page.ns) {
+                trace!("Skipping page '{}' in namespace {}", page.title, page.ns);
+                continue;
+            }
+        }
+        if let Some(redirect) = &page.redirect {
+            if skip_redirects {
+                info!("Page '{}' is a redirect, skipping", page.title);
+                continue;
+            }
+            trace!("Page '{}' redirects to '{}'", page.title, redirect.title);
+        }
+        if let Some(0) = page_count {
+            trace!("Reached page count limit, stopping");
+            return Ok(());
+        }
+        page_count = page_count.map(|count| count - 1);
+
+        info!("Read page '{}'", page.title);
+        sender.blocking_send(DumpItem::Page(page.clone())).unwrap();
+
+        let mut revision_count = revision_count;
+        while let Some(revision) = reader.next_revision()? {
+            if let Some(0) = revision_count {
+                trace!("Reached revision count limit, skipping the rest of the page");
+                break;
+            }
+            revision_count = revision_count.map(|count| count - 1);
+
+            let revision = get_parsed_revision_from_dump(revision, &page);
+            trace!(
+                "Sending revision {} of page '{}'",
+                revision.revid,
+                page.title
+            );
+            sender.blocking_send(DumpItem::Revision(revision)).unwrap();
+        }
+
+        if uploads {
+            while let Some(upload) = reader.next_upload()? {
+                let upload = get_parsed_upload_from_dump(upload, &page);
+                trace!(
+                    "Sending upload of {} for page '{}'",
+                    upload.timestamp,
+                    page.title
+                );
+                sender.blocking_send(DumpItem::Upload(upload)).unwrap();
+            }
+        }
+
+        sender.blocking_send(DumpItem::PageEnd).unwrap();
+    }
+    Ok(())
+}
+
 async fn task_process_revision(
-    author_data: &AuthorData,
+    conversion: &mut Conversion,
+    site_info: &SiteInfo,
     revision: ParsedRevision,
-    repository: &mut Repository,
-    repository_path: &Path,
     namespace: u32,
 ) -> Result<(), std::io::Error> {
     info!(
         "Processing revision {} of page '{}'",
         revision.revid, revision.title
     );
+    debug!(
+        parentid = revision.parentid,
+        minor = revision.minor,
+        sha1 = revision.sha1,
+        model = revision.model,
+        format = revision.format,
+        "Revision metadata"
+    );
+    if revision.text_hidden {
+        if let HiddenText::Skip = conversion.hidden_text {
+            info!(
+                "Skipping revision {} of page '{}', whose content was hidden",
+                revision.revid, revision.title
+            );
+            return Ok(());
+        }
+    }
+    let redirect_target = if is_wikitext(revision.model.as_deref()) {
+        get_redirect_target(&revision.content)
+    } else {
+        None
+    };
+    if let (Some(target), Redirects::Skip) = (redirect_target, conversion.redirects) {
+        info!(
+            "Skipping revision {} of page '{}', which redirects to '{}'",
+            revision.revid, revision.title, target
+        );
+        return Ok(());
+    }
 
-    let authors = &author_data.authors;
+    let file_path = get_page_file_name(
+        &revision.title,
+        namespace,
+        revision.model.as_deref(),
+        site_info,
+    );
+    let branch_name = get_branch_name(&revision.title, namespace, site_info);
+    let file_paths =
+        if revision.text_hidden && matches!(conversion.hidden_text, HiddenText::KeepPrevious) {
+            Vec::new()
+        } else {
+            write_revision_files(conversion, site_info, &revision, file_path, &branch_name).await?
+        };
 
-    let file_path = get_file_name(&revision.title, namespace);
-    let branch_name = get_branch_name(&revision.title, namespace);
-    let absolute_file_path = repository_path.join(&file_path);
+    let author_git_data = get_author_git_data(&conversion.author_data, &revision.user);
+    let author = get_signature(revision.timestamp, &author_git_data);
+    let committer = Signature::new("name", "email", &Time::new(0, 0)).unwrap();
+    // The page's file is written first, and is a symbolic link for redirects
+    let symlink_paths = match (redirect_target, conversion.redirects, file_paths.first()) {
+        (Some(_), Redirects::Symlink, Some(file_path)) => vec![file_path.as_path()],
+        _ => Vec::new(),
+    };
+
+    create_commit_from_metadata(
+        &mut conversion.repository,
+        committer,
+        author,
+        &branch_name,
+        &file_paths.iter().map(PathBuf::as_path).collect::<Vec<_>>(),
+        &symlink_paths,
+        &get_commit_message(&revision),
+    );
+    conversion
+        .imported_revisions
+        .insert(revision.pageid, revision.revid);
+
+    Ok(())
+}
+
+/// Writes the page and its other slots to the working directory, and returns the
+/// paths to stage, including those of slots the page doesn't have anymore
+async fn write_revision_files(
+    conversion: &mut Conversion,
+    site_info: &SiteInfo,
+    revision: &ParsedRevision,
+    file_path: PathBuf,
+    branch_name: &str,
+) -> Result<Vec<PathBuf>, std::io::Error> {
+    let absolute_file_path = conversion.output_dir.join(&file_path);
 
     // create parent directories if necessary
     if let Some(parent) = absolute_file_path.parent() {
@@ -295,44 +1531,271 @@ async fn task_process_revision(
         tokio::fs::create_dir_all(parent).await?;
     }
 
-    // execute pandoc command with revision.content as input and write to file_path
-    let title = revision.title.clone();
-    let content = revision.content.clone();
-    spawn(async move {
-        convert_file(&absolute_file_path, &title, &content);
-    })
-    .await
-    .unwrap();
-
-    let author_git_data = if let Some(author_git_data) = authors.get(&revision.user) {
-        author_git_data.clone()
+    let redirect_target = if is_wikitext(revision.model.as_deref()) {
+        get_redirect_target(&revision.content)
     } else {
-        if !authors.is_empty() {
-            warn!(
-                "No git author data found for wiki author '{}', commit will have empty email",
-                revision.user
+        None
+    };
+    match (redirect_target, conversion.redirects) {
+        (Some(target), Redirects::Stub | Redirects::Symlink | Redirects::Map) => {
+            write_redirect(conversion, site_info, &revision.title, &file_path, target).await?;
+        }
+        _ => {
+            conversion
+                .redirect_map
+                .remove(file_path.to_string_lossy().as_ref());
+
+            if revision.text_hidden {
+                // Only reached for `HiddenText::Empty`, which leaves nothing for pandoc
+                trace!("Writing empty file '{}'", file_path.to_string_lossy());
+                tokio::fs::write(&absolute_file_path, "").await?;
+            } else {
+                // execute pandoc command with revision.content as input and write to file_path,
+                // unless it's code or data that is stored as is
+                let title = revision.title.clone();
+                let content = revision.content.clone();
+                let model = revision.model.clone();
+                spawn(async move {
+                    write_page(&absolute_file_path, &title, &content, model.as_deref());
+                })
+                .await
+                .unwrap();
+            }
+        }
+    }
+
+    // Other slots are written as is next to the page, and removed once the
+    // page doesn't have them anymore
+    let mut file_paths =
+        get_slot_file_names(&conversion.repository, branch_name, &file_path).unwrap_or_default();
+    for slot_file_path in &file_paths {
+        let absolute_slot_file_path = conversion.output_dir.join(slot_file_path);
+        if absolute_slot_file_path.exists() {
+            tokio::fs::remove_file(absolute_slot_file_path).await?;
+        }
+    }
+    for slot in &revision.slots {
+        let slot_file_path = get_slot_file_name(&file_path, &slot.role, slot.model.as_deref());
+        trace!(
+            "Writing slot '{}' to '{}'",
+            slot.role,
+            slot_file_path.display()
+        );
+        tokio::fs::write(conversion.output_dir.join(&slot_file_path), &slot.content).await?;
+        if !file_paths.contains(&slot_file_path) {
+            file_paths.push(slot_file_path);
+        }
+    }
+    file_paths.insert(0, file_path);
+
+    Ok(file_paths)
+}
+
+/// Writes the file of a redirect page as a stub or a symbolic link, or removes it
+/// and adds the redirect to the map, depending on `conversion.redirects`
+async fn write_redirect(
+    conversion: &mut Conversion,
+    site_info: &SiteInfo,
+    title: &str,
+    file_path: &Path,
+    target: &str,
+) -> Result<(), std::io::Error> {
+    trace!("Page '{}' redirects to '{}'", title, target);
+    let (target_title, section) = match target.split_once('#') {
+        Some((target_title, section)) => (target_title, Some(section)),
+        None => (target, None),
+    };
+    let (target_title, target_namespace) = site_info.parse_title(target_title);
+    let target_file_path = get_file_name(&target_title, target_namespace, site_info);
+    let anchor = section
+        .map(|section| format!("#{}", get_anchor(section)))
+        .unwrap_or_default();
+
+    let absolute_file_path = conversion.output_dir.join(file_path);
+    let relative_path = get_relative_path(file_path, &target_file_path);
+    match conversion.redirects {
+        Redirects::Stub => {
+            // File names are escaped, so their percent signs have to be escaped too
+            let link = relative_path
+                .iter()
+                .map(|component| urlencoding::encode(&component.to_string_lossy()).into_owned())
+                .collect::<Vec<_>>()
+                .join("/");
+            let link = format!("{}{}", link, anchor);
+            write_redirect_stub(&absolute_file_path, title, target, &link);
+        }
+        Redirects::Symlink => {
+            // Without core.symlinks, the link is checked out as a file holding its target
+            tokio::fs::write(
+                &absolute_file_path,
+                relative_path.to_string_lossy().as_bytes(),
+            )
+            .await?;
+        }
+        _ => {
+            if absolute_file_path.exists() {
+                tokio::fs::remove_file(&absolute_file_path).await?;
+            }
+            conversion.redirect_map.insert(
+                file_path.to_string_lossy().into_owned(),
+                format!("{}{}", target_file_path.to_string_lossy(), anchor),
             );
         }
-        // git2-rs doesn't let us create a commit with no email, so we use a dummy email
-        Author {
-            name: revision.user.clone(),
-            email: "no-email@example.com".to_string(),
+    }
+    Ok(())
+}
+
+async fn task_process_upload(
+    conversion: &mut Conversion,
+    site_info: &SiteInfo,
+    client: &ApiClient,
+    upload: ParsedUpload,
+) -> Result<(), ApiError> {
+    info!(
+        "Processing upload of {} for page '{}'",
+        upload.timestamp, upload.title
+    );
+    debug!(sha1 = upload.sha1, "Upload metadata");
+
+    let data = match &upload.contents {
+        UploadContents::Data(data) => data.clone(),
+        UploadContents::Url(url) => {
+            trace!("Downloading '{}'", url);
+            client.download(url).await?
+        }
+        UploadContents::Missing => {
+            warn!("Upload of {} has no contents, skipping", upload.timestamp);
+            return Ok(());
         }
     };
 
-    let author = get_signature(&revision, &author_git_data);
-    let committer = Signature::new("name", "email", &Time::new(0, 0)).unwrap();
+    let file_path = get_upload_file_name(&upload.title, site_info);
+    let branch_name = get_branch_name(&upload.title, FILE_NAMESPACE, site_info);
+    let absolute_file_path = conversion.output_dir.join(&file_path);
+    let written = async {
+        if let Some(parent) = absolute_file_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&absolute_file_path, data).await
+    }
+    .await;
+    if let Err(error) = written {
+        error!(
+            "Failed to write upload of {} to '{}', skipping: {}",
+            upload.timestamp,
+            file_path.display(),
+            error
+        );
+        return Ok(());
+    }
 
+    let author_git_data = get_author_git_data(&conversion.author_data, &upload.user);
+    let author = get_signature(upload.timestamp, &author_git_data);
     create_commit_from_metadata(
-        repository,
-        committer,
+        &mut conversion.repository,
+        conversion.committer.clone(),
         author,
         &branch_name,
-        &file_path,
-        &revision.comment,
+        &[&file_path],
+        &[],
+        &get_upload_commit_message(&upload),
     );
+    conversion
+        .imported_uploads
+        .insert(upload.pageid, upload.timestamp);
 
     Ok(())
 }
 
+fn get_author_git_data(author_data: &AuthorData, contributor: &Contributor) -> Author {
+    let authors = &author_data.authors;
+    let name = match contributor {
+        Contributor::User(name) => {
+            if let Some(author_git_data) = authors.get(name) {
+                return author_git_data.clone();
+            }
+            if !authors.is_empty() {
+                warn!(
+                    "No git author data found for wiki author '{}', commit will have empty email",
+                    name
+                );
+            }
+            name.clone()
+        }
+        // Anonymous users aren't expected to be in the author data
+        Contributor::Ip(ip) => ip.clone(),
+        Contributor::Hidden => {
+            trace!("Contributor was hidden, using placeholder author");
+            contributor.to_string()
+        }
+    };
+
+    // git2-rs doesn't let us create a commit with no email, so we use a dummy email
+    Author {
+        name,
+        email: "no-email@example.com".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle_git::get_most_recent_commit;
+    use crate::handle_git::tests::create_test_repo;
+
+    #[tokio::test]
+    async fn test_hidden_text_empty() {
+        let (repository, output_dir, committer) = create_test_repo("test_hidden_text_empty");
+        let mut conversion = Conversion {
+            client: ApiClient::new(ClientOptions::default()),
+            author_data: AuthorData::default(),
+            committer,
+            repository,
+            output_dir: output_dir.clone(),
+            imported_revisions: HashMap::new(),
+            imported_uploads: HashMap::new(),
+            hidden_text: HiddenText::Empty,
+            redirects: Redirects::Convert,
+            redirect_map: BTreeMap::new(),
+        };
+
+        let site_info = SiteInfo::default();
+        let revision = ParsedRevision {
+            revid: 1,
+            parentid: Some(0),
+            pageid: 1,
+            timestamp: time::macros::datetime!(2023-01-23 08:34:52),
+            title: "Hidden".to_string(),
+            user: Contributor::Hidden,
+            minor: false,
+            comment: String::new(),
+            sha1: None,
+            model: Some("wikitext".to_string()),
+            format: Some("text/x-wiki".to_string()),
+            content: String::new(),
+            text_hidden: true,
+            slots: Vec::new(),
+        };
+        let branch_name = get_branch_name("Hidden", 0, &site_info);
+        prepare_branch(&conversion, &branch_name, 1);
+        task_process_revision(&mut conversion, &site_info, revision, 0)
+            .await
+            .unwrap();
+
+        let file_path = get_page_file_name("Hidden", 0, None, &site_info);
+        let blob = get_most_recent_commit(&conversion.repository, &branch_name)
+            .unwrap()
+            .tree()
+            .unwrap()
+            .get_path(&file_path)
+            .unwrap()
+            .to_object(&conversion.repository)
+            .unwrap()
+            .peel_to_blob()
+            .unwrap();
+        assert!(blob.content().is_empty());
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
+
 // TODO - switch to gix and bstring
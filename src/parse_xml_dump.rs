@@ -1,10 +1,19 @@
-#![allow(unused)]
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
 
-use std::path::PathBuf;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
 
-use serde::Deserialize;
+use quick_xml::events::{BytesEnd, BytesStart, Event};
+use quick_xml::name::QName;
+use quick_xml::{DeError, Reader, Writer};
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, PrimitiveDateTime};
+use tracing::{info, trace, warn};
 
-//use crate::fetch_revisions::ParsedRevision;
+use crate::fetch_revisions::{Contributor, ParsedRevision, ParsedSlot};
 
 /*
 Original PHP code:
@@ -91,49 +100,1036 @@ public function convertData()
 
 */
 
-#[derive(Debug, Default, Deserialize)]
-pub struct MediaWikiDump {
-    #[serde(rename = "page")]
-    pages: Vec<PageDump>,
+/// The `<siteinfo>` block at the start of a dump
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SiteInfo {
+    pub sitename: String,
+    pub dbname: String,
+    /// URL of the main page
+    pub base: String,
+    /// MediaWiki version, e.g. "MediaWiki 1.39.4"
+    pub generator: String,
+    /// Either "first-letter" or "case-sensitive"
+    pub case: String,
+    pub namespaces: NamespaceList,
+    /// Path of `index.php`, e.g. "/w/index.php". Only known from the API
+    #[serde(skip)]
+    pub script: Option<String>,
+    /// Path of articles, with "$1" for the title, e.g. "/wiki/$1". Only known
+    /// from the API
+    #[serde(skip)]
+    pub article_path: Option<String>,
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct NamespaceList {
+    #[serde(rename = "namespace", default)]
+    pub namespaces: Vec<NamespaceInfo>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct NamespaceInfo {
+    #[serde(rename = "@key")]
+    pub key: i32,
+    #[serde(rename = "@case", default)]
+    pub case: String,
+    /// Localized prefix of the namespace, empty for the main namespace
+    #[serde(rename = "$text", default)]
+    pub name: String,
+}
+
+impl SiteInfo {
+    pub fn namespace_name(&self, namespace: u32) -> Option<&str> {
+        self.namespaces
+            .namespaces
+            .iter()
+            .find(|info| i64::from(info.key) == i64::from(namespace))
+            .map(|info| info.name.as_str())
+    }
+
+    /// Returns the normalized full title and the namespace of a title as written in
+    /// a link, e.g. ("Help:Foo bar", 12) for "help:foo_bar".
+    pub fn parse_title(&self, title: &str) -> (String, u32) {
+        let title = title.replace('_', " ");
+        let title = title.trim();
+        let namespace = title.split_once(':').and_then(|(prefix, name)| {
+            let prefix = prefix.trim().to_lowercase();
+            self.namespaces
+                .namespaces
+                .iter()
+                .find(|info| info.key > 0 && info.name.to_lowercase() == prefix)
+                .map(|info| (info, name.trim()))
+        });
+        let (prefix, name, case, namespace) = match namespace {
+            Some((info, name)) => (
+                info.name.as_str(),
+                name,
+                info.case.as_str(),
+                info.key as u32,
+            ),
+            None => ("", title, self.case.as_str(), 0),
+        };
+
+        let mut chars = name.chars();
+        let name = match chars.next() {
+            Some(first) if case == "first-letter" => first.to_uppercase().chain(chars).collect(),
+            _ => name.to_string(),
+        };
+        if prefix.is_empty() {
+            (name, namespace)
+        } else {
+            (format!("{}:{}", prefix, name), namespace)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct PageDump {
-    title: String,
-    #[serde(rename = "revision")]
-    revisions: Vec<RevisionDump>,
+    pub title: String,
+    pub ns: u32,
+    pub id: u64,
+    /// Set if the current revision of the page is a redirect
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<RedirectDump>,
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RedirectDump {
+    #[serde(rename = "@title")]
+    pub title: String,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct RevisionDump {
-    timestamp: String,
-    contributor: ContributorDump,
-    comment: String,
-    text: String,
+    pub id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parentid: Option<u64>,
+    pub timestamp: String,
+    pub contributor: ContributorDump,
+    /// `<minor/>` is only present for minor edits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minor: Option<()>,
+    /// Missing if the comment is empty
+    #[serde(default)]
+    pub comment: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    pub text: String,
+    /// Whether the text was hidden by revision deletion, as in `<text deleted="deleted" />`
+    #[serde(skip)]
+    pub text_deleted: bool,
+    /// The slots other than "main", in dumps of wikis with Multi-Content Revisions
+    #[serde(rename = "content", default, skip_serializing_if = "Vec::is_empty")]
+    pub contents: Vec<ContentDump>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha1: Option<String>,
+}
+
+/// A `<content>` element, holding a slot of a revision other than "main"
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ContentDump {
+    pub role: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    pub text: String,
+}
+
+/// An `<upload>` element, describing one version of an uploaded file
+#[derive(Debug, Default, Deserialize)]
+pub struct UploadDump {
+    pub timestamp: String,
+    pub contributor: ContributorDump,
+    #[serde(default)]
+    pub comment: String,
+    pub filename: String,
+    /// URL of the file on the wiki
+    pub src: Option<String>,
+    pub size: Option<u64>,
+    pub sha1base36: Option<String>,
+    /// Only present if the dump was made with `--include-files`
+    pub contents: Option<UploadContentsDump>,
 }
 
 #[derive(Debug, Default, Deserialize)]
+pub struct UploadContentsDump {
+    #[serde(rename = "@encoding", default)]
+    pub encoding: String,
+    #[serde(rename = "$text", default)]
+    pub data: String,
+}
+
+/// A `<contributor>` element, which can have one of these forms:
+/// - `<contributor><username>Foo</username><id>42</id></contributor>`
+/// - `<contributor><ip>127.0.0.1</ip></contributor>` for anonymous edits
+/// - `<contributor deleted="deleted" />` if the user was hidden
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ContributorDump {
-    username: String,
+    #[serde(rename = "@deleted", skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+}
+
+impl From<ContributorDump> for Contributor {
+    fn from(contributor: ContributorDump) -> Self {
+        match contributor {
+            ContributorDump {
+                deleted: Some(_), ..
+            } => Contributor::Hidden,
+            ContributorDump {
+                username: Some(username),
+                ..
+            } => Contributor::User(username),
+            ContributorDump { ip: Some(ip), .. } => Contributor::Ip(ip),
+            _ => Contributor::Hidden,
+        }
+    }
+}
+
+/// The content of a `<text>` element as is
+struct RawText {
+    /// Depth in the element read, 1 being the element itself
+    depth: usize,
+    text: String,
+    /// Whether the element has a `deleted` attribute
+    deleted: bool,
 }
 
-pub fn get_revisions_from_xml(path: PathBuf) -> MediaWikiDump {
-    let file = std::fs::File::open(path).unwrap();
-    let reader = std::io::BufReader::new(file);
+/// Pull-based reader for MediaWiki XML dumps.
+///
+/// Only one revision is held in memory at a time, so arbitrarily large
+/// full-history dumps can be converted.
+pub struct DumpReader<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    /// Whether we're between the start and end tags of a `<page>` element
+    in_page: bool,
+    /// A `<revision>` or `<upload>` start tag that was read but not handled yet
+    pending_element: Option<BytesStart<'static>>,
+    site_info: Option<SiteInfo>,
+}
 
-    let result: MediaWikiDump = serde_xml_rs::from_reader(reader).unwrap();
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Bzip2,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression of a file from its first bytes, falling back
+    /// to its extension.
+    pub fn detect(header: &[u8], path: &Path) -> Self {
+        if header.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            match path.extension().and_then(|extension| extension.to_str()) {
+                Some("bz2") => Compression::Bzip2,
+                Some("gz") => Compression::Gzip,
+                Some("zst") => Compression::Zstd,
+                _ => Compression::None,
+            }
+        }
+    }
 
-    result
+    fn decoder<'a>(self, reader: impl BufRead + 'a) -> std::io::Result<Box<dyn BufRead + 'a>> {
+        Ok(match self {
+            Compression::None => Box::new(reader),
+            // Wikimedia publishes "multistream" dumps, which are several
+            // concatenated bzip2 streams
+            Compression::Bzip2 => Box::new(BufReader::new(MultiBzDecoder::new(reader))),
+            Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+            Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+        })
+    }
+}
+
+impl DumpReader<Box<dyn BufRead>> {
+    /// Opens a dump, decompressing it on the fly if it's compressed.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let compression = Compression::detect(file.fill_buf()?, path);
+        if compression != Compression::None {
+            info!("Reading {:?}-compressed dump", compression);
+        }
+        Ok(Self::new(compression.decoder(file)?))
+    }
+}
+
+impl<R: BufRead> DumpReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: Reader::from_reader(reader),
+            buf: Vec::new(),
+            in_page: false,
+            pending_element: None,
+            site_info: None,
+        }
+    }
+
+    /// Returns the `<siteinfo>` block of the dump, if it was read by the last
+    /// call to `next_page`.
+    pub fn take_site_info(&mut self) -> Option<SiteInfo> {
+        self.site_info.take()
+    }
+
+    /// Skips to the next `<page>` element and returns its metadata.
+    ///
+    /// Call `next_revision` to get the revisions of the page. Revisions of the
+    /// previous page that weren't read are skipped.
+    pub fn next_page(&mut self) -> Result<Option<PageDump>, DeError> {
+        if self.in_page {
+            self.skip_page()?;
+        }
+
+        loop {
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf)? {
+                Event::Start(start) if start.name().as_ref() == b"page" => break,
+                // We need to go inside the root element to find pages
+                Event::Start(start) if start.name().as_ref() == b"mediawiki" => {}
+                Event::Start(start) if start.name().as_ref() == b"siteinfo" => {
+                    let start = start.into_owned();
+                    let element = self.read_element(start)?;
+                    self.site_info = Some(quick_xml::de::from_reader(element.as_slice())?);
+                }
+                Event::Start(start) => {
+                    let name = start.name().as_ref().to_vec();
+                    trace!("Skipping <{}> element", String::from_utf8_lossy(&name));
+                    self.reader.read_to_end_into(QName(&name), &mut self.buf)?;
+                }
+                Event::Eof => return Ok(None),
+                _ => {}
+            }
+        }
+        self.in_page = true;
+
+        // Copy every child of the page up to the first revision, and deserialize
+        // them as a standalone page.
+        let mut header = Writer::new(Vec::new());
+        header.write_event(Event::Start(BytesStart::new("page")))?;
+        loop {
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf)? {
+                Event::Start(start) if matches!(start.name().as_ref(), b"revision" | b"upload") => {
+                    self.pending_element = Some(start.into_owned());
+                    break;
+                }
+                Event::Start(start) => {
+                    let start = start.into_owned();
+                    let element = self.read_element(start)?;
+                    header.get_mut().extend_from_slice(&element);
+                }
+                Event::Empty(empty) => header.write_event(Event::Empty(empty))?,
+                Event::End(end) if end.name().as_ref() == b"page" => {
+                    self.in_page = false;
+                    break;
+                }
+                Event::Eof => return Err(DeError::UnexpectedEof),
+                _ => {}
+            }
+        }
+        header.write_event(Event::End(BytesEnd::new("page")))?;
+
+        let page = quick_xml::de::from_reader(header.into_inner().as_slice())?;
+        Ok(Some(page))
+    }
+
+    /// Returns the next revision of the current page, or `None` once all of them
+    /// have been read.
+    pub fn next_revision(&mut self) -> Result<Option<RevisionDump>, DeError> {
+        match self.pending_element.take() {
+            Some(start) if start.name().as_ref() == b"revision" => {
+                return self.read_revision(start).map(Some);
+            }
+            // Uploads come after every revision
+            Some(start) => {
+                self.pending_element = Some(start);
+                return Ok(None);
+            }
+            None => {}
+        }
+        if !self.in_page {
+            return Ok(None);
+        }
+
+        loop {
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf)? {
+                Event::Start(start) if start.name().as_ref() == b"revision" => {
+                    let start = start.into_owned();
+                    return self.read_revision(start).map(Some);
+                }
+                Event::Start(start) if start.name().as_ref() == b"upload" => {
+                    self.pending_element = Some(start.into_owned());
+                    return Ok(None);
+                }
+                Event::Start(start) => {
+                    let name = start.name().as_ref().to_vec();
+                    trace!("Skipping <{}> element", String::from_utf8_lossy(&name));
+                    self.reader.read_to_end_into(QName(&name), &mut self.buf)?;
+                }
+                Event::End(end) if end.name().as_ref() == b"page" => {
+                    self.in_page = false;
+                    return Ok(None);
+                }
+                Event::Eof => return Err(DeError::UnexpectedEof),
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the next version of the file uploaded to the current page, oldest
+    /// first, or `None` once all of them have been read. Only dumps made with
+    /// `--uploads` have them.
+    ///
+    /// Revisions of the page that weren't read are skipped.
+    pub fn next_upload(&mut self) -> Result<Option<UploadDump>, DeError> {
+        if let Some(start) = self.pending_element.take() {
+            if start.name().as_ref() == b"upload" {
+                let element = self.read_element(start)?;
+                return quick_xml::de::from_reader(element.as_slice()).map(Some);
+            }
+            self.buf.clear();
+            self.reader.read_to_end_into(start.name(), &mut self.buf)?;
+        }
+        if !self.in_page {
+            return Ok(None);
+        }
+
+        loop {
+            self.buf.clear();
+            match self.reader.read_event_into(&mut self.buf)? {
+                Event::Start(start) if start.name().as_ref() == b"upload" => {
+                    let start = start.into_owned();
+                    let element = self.read_element(start)?;
+                    return quick_xml::de::from_reader(element.as_slice()).map(Some);
+                }
+                Event::Start(start) => {
+                    let name = start.name().as_ref().to_vec();
+                    trace!("Skipping <{}> element", String::from_utf8_lossy(&name));
+                    self.reader.read_to_end_into(QName(&name), &mut self.buf)?;
+                }
+                Event::End(end) if end.name().as_ref() == b"page" => {
+                    self.in_page = false;
+                    return Ok(None);
+                }
+                Event::Eof => return Err(DeError::UnexpectedEof),
+                _ => {}
+            }
+        }
+    }
+
+    fn read_revision(&mut self, start: BytesStart<'static>) -> Result<RevisionDump, DeError> {
+        let (element, texts) = self.read_element_with_texts(start)?;
+        let mut revision: RevisionDump = quick_xml::de::from_reader(element.as_slice())?;
+
+        // The deserializer trims text, but leading spaces are meaningful in wikitext
+        let mut contents = revision.contents.iter_mut();
+        for RawText {
+            depth,
+            text,
+            deleted,
+        } in texts
+        {
+            match depth {
+                2 => {
+                    revision.text = text;
+                    revision.text_deleted = deleted;
+                }
+                3 => {
+                    if let Some(content) = contents.next() {
+                        content.text = text;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(revision)
+    }
+
+    /// Copies the element whose start tag was just read, up to and including its
+    /// end tag, into a standalone buffer.
+    fn read_element(&mut self, start: BytesStart<'static>) -> Result<Vec<u8>, DeError> {
+        self.read_element_with_texts(start)
+            .map(|(element, _)| element)
+    }
+
+    /// Like `read_element`, but also returns the content of the `<text>` elements
+    /// it contains as is.
+    fn read_element_with_texts(
+        &mut self,
+        start: BytesStart<'static>,
+    ) -> Result<(Vec<u8>, Vec<RawText>), DeError> {
+        let mut writer = Writer::new(Vec::new());
+        writer.write_event(Event::Start(start))?;
+
+        let mut texts = Vec::new();
+        let is_deleted = |element: &BytesStart| {
+            element
+                .attributes()
+                .flatten()
+                .any(|attribute| attribute.key.as_ref() == b"deleted")
+        };
+        // Depth of the `<text>` element being read, if any
+        let mut text_depth = None;
+        let mut depth = 1;
+        while depth > 0 {
+            self.buf.clear();
+            let event = self.reader.read_event_into(&mut self.buf)?;
+            match &event {
+                Event::Start(start) => {
+                    depth += 1;
+                    if start.name().as_ref() == b"text" {
+                        text_depth = Some(depth);
+                        texts.push(RawText {
+                            depth,
+                            text: String::new(),
+                            deleted: is_deleted(start),
+                        });
+                    }
+                }
+                Event::Empty(empty) if empty.name().as_ref() == b"text" => {
+                    texts.push(RawText {
+                        depth: depth + 1,
+                        text: String::new(),
+                        deleted: is_deleted(empty),
+                    });
+                }
+                Event::End(_) => {
+                    if text_depth == Some(depth) {
+                        text_depth = None;
+                    }
+                    depth -= 1;
+                }
+                Event::Text(text) if text_depth.is_some() => {
+                    texts.last_mut().unwrap().text.push_str(&text.unescape()?);
+                }
+                Event::CData(cdata) if text_depth.is_some() => {
+                    let cdata = String::from_utf8_lossy(cdata);
+                    texts.last_mut().unwrap().text.push_str(&cdata);
+                }
+                Event::Eof => return Err(DeError::UnexpectedEof),
+                _ => {}
+            }
+            writer.write_event(event)?;
+        }
+
+        Ok((writer.into_inner(), texts))
+    }
+
+    fn skip_page(&mut self) -> Result<(), DeError> {
+        if let Some(start) = self.pending_element.take() {
+            self.buf.clear();
+            self.reader.read_to_end_into(start.name(), &mut self.buf)?;
+        }
+        self.buf.clear();
+        self.reader
+            .read_to_end_into(QName(b"page"), &mut self.buf)?;
+        self.in_page = false;
+        Ok(())
+    }
+}
+
+/// Maps a dumped revision to the same type the API fetcher produces,
+/// so that both can go through the same git pipeline.
+pub fn get_parsed_revision_from_dump(revision: RevisionDump, page: &PageDump) -> ParsedRevision {
+    ParsedRevision {
+        revid: revision.id,
+        parentid: revision.parentid,
+        pageid: page.id,
+        timestamp: PrimitiveDateTime::parse(&revision.timestamp, &Rfc3339).unwrap(),
+        title: page.title.clone(),
+        user: revision.contributor.into(),
+        minor: revision.minor.is_some(),
+        comment: revision.comment,
+        sha1: revision.sha1,
+        model: revision.model,
+        format: revision.format,
+        content: revision.text,
+        text_hidden: revision.text_deleted,
+        slots: revision
+            .contents
+            .into_iter()
+            .map(|content| ParsedSlot {
+                role: content.role,
+                model: content.model,
+                content: content.text,
+            })
+            .collect(),
+    }
+}
+
+/// A version of an uploaded file
+#[derive(Debug)]
+pub struct ParsedUpload {
+    pub pageid: u64,
+    pub title: String,
+    pub timestamp: PrimitiveDateTime,
+    pub user: Contributor,
+    pub comment: String,
+    pub sha1: Option<String>,
+    pub contents: UploadContents,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum UploadContents {
+    /// The file was embedded in the dump
+    Data(Vec<u8>),
+    /// The file has to be downloaded from the wiki
+    Url(String),
+    /// Neither the file nor its URL are in the dump
+    Missing,
+}
+
+pub fn get_parsed_upload_from_dump(upload: UploadDump, page: &PageDump) -> ParsedUpload {
+    let contents = match (upload.contents, upload.src) {
+        (Some(contents), src) if contents.encoding == "base64" => {
+            // The data is split into lines
+            let data: String = contents.data.split_whitespace().collect();
+            match BASE64_STANDARD.decode(data) {
+                Ok(data) => {
+                    if upload.size.is_some_and(|size| size != data.len() as u64) {
+                        warn!(
+                            "Upload '{}' is {} bytes long, expected {:?}",
+                            upload.filename,
+                            data.len(),
+                            upload.size
+                        );
+                    }
+                    UploadContents::Data(data)
+                }
+                // e.g. if the dump was truncated
+                Err(error) => {
+                    warn!(
+                        "Invalid contents for upload '{}': {}",
+                        upload.filename, error
+                    );
+                    src.map_or(UploadContents::Missing, UploadContents::Url)
+                }
+            }
+        }
+        (Some(contents), src) => {
+            warn!(
+                "Unknown encoding '{}' for upload '{}'",
+                contents.encoding, upload.filename
+            );
+            src.map_or(UploadContents::Missing, UploadContents::Url)
+        }
+        (None, Some(src)) => UploadContents::Url(src),
+        (None, None) => UploadContents::Missing,
+    };
+    ParsedUpload {
+        pageid: page.id,
+        title: page.title.clone(),
+        timestamp: PrimitiveDateTime::parse(&upload.timestamp, &Rfc3339).unwrap(),
+        user: upload.contributor.into(),
+        comment: upload.comment,
+        sha1: upload.sha1base36,
+        contents,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use insta::assert_debug_snapshot;
+    use std::io::Read;
+
+    const ARCH_DUMP: &str = "test_files/ArchWiki-20230802150007.xml";
+
+    fn read_all_pages(reader: &mut DumpReader<impl BufRead>) -> Vec<(PageDump, Vec<RevisionDump>)> {
+        let mut pages = Vec::new();
+        while let Some(page) = reader.next_page().unwrap() {
+            let mut revisions = Vec::new();
+            while let Some(revision) = reader.next_revision().unwrap() {
+                revisions.push(revision);
+            }
+            pages.push((page, revisions));
+        }
+        pages
+    }
 
     #[test]
     fn test_load_arch_dump() {
-        let dump = get_revisions_from_xml(PathBuf::from("test_files/ArchWiki-20230802150007.xml"));
-        assert_debug_snapshot!(dump);
+        let mut reader = DumpReader::open(Path::new(ARCH_DUMP)).unwrap();
+        let pages = read_all_pages(&mut reader);
+        assert_debug_snapshot!(pages);
+    }
+
+    #[test]
+    fn test_load_compressed_dumps() {
+        let dump = std::fs::read(ARCH_DUMP).unwrap();
+        let expected = read_all_pages(&mut DumpReader::new(dump.as_slice()));
+
+        let mut bzip2_dump = Vec::new();
+        bzip2::read::BzEncoder::new(dump.as_slice(), bzip2::Compression::fast())
+            .read_to_end(&mut bzip2_dump)
+            .unwrap();
+        let mut gzip_dump = Vec::new();
+        flate2::read::GzEncoder::new(dump.as_slice(), flate2::Compression::fast())
+            .read_to_end(&mut gzip_dump)
+            .unwrap();
+        let zstd_dump = zstd::encode_all(dump.as_slice(), 0).unwrap();
+
+        // Extensions don't match the contents, to check that we look at magic bytes
+        for (name, compressed, compression) in [
+            ("dump.xml.bz2", bzip2_dump, Compression::Bzip2),
+            ("dump.xml.zst", gzip_dump, Compression::Gzip),
+            ("dump.xml.gz", zstd_dump, Compression::Zstd),
+        ] {
+            let path = std::env::temp_dir().join(format!("test_load_compressed_{name}"));
+            std::fs::write(&path, &compressed).unwrap();
+            assert_eq!(Compression::detect(&compressed, &path), compression);
+
+            let pages = read_all_pages(&mut DumpReader::open(&path).unwrap());
+            assert_eq!(format!("{:?}", pages), format!("{:?}", expected));
+            std::fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(
+            Compression::detect(b"", Path::new("dump.xml.bz2")),
+            Compression::Bzip2
+        );
+        assert_eq!(
+            Compression::detect(b"<mediawiki", Path::new("dump.xml")),
+            Compression::None
+        );
+    }
+
+    #[test]
+    fn test_load_arch_site_info() {
+        let mut reader = DumpReader::open(Path::new(ARCH_DUMP)).unwrap();
+        reader.next_page().unwrap();
+        let site_info = reader.take_site_info().unwrap();
+
+        assert_eq!(site_info.sitename, "ArchWiki");
+        assert_eq!(site_info.base, "https://wiki.archlinux.org/title/Main_page");
+        assert_eq!(site_info.case, "first-letter");
+        assert_eq!(site_info.namespaces.namespaces.len(), 20);
+        assert_eq!(site_info.namespace_name(0), Some(""));
+        assert_eq!(site_info.namespace_name(3), Some("User talk"));
+        assert_eq!(site_info.namespace_name(3000), Some("DeveloperWiki"));
+        assert_eq!(site_info.namespace_name(42), None);
+        assert_eq!(
+            site_info.parse_title("user_talk: foo"),
+            ("User talk:Foo".to_string(), 3)
+        );
+        assert_eq!(
+            site_info.parse_title("Unknown:foo"),
+            ("Unknown:foo".to_string(), 0)
+        );
+        assert_eq!(
+            site_info.namespaces.namespaces[0],
+            NamespaceInfo {
+                key: -2,
+                case: "first-letter".into(),
+                name: "Media".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parsed_revision_from_dump() {
+        let mut reader = DumpReader::open(Path::new(ARCH_DUMP)).unwrap();
+        let page = reader.next_page().unwrap().unwrap();
+        let revision = reader.next_revision().unwrap().unwrap();
+        let revision = get_parsed_revision_from_dump(revision, &page);
+
+        assert_eq!(revision.revid, 764858);
+        assert_eq!(revision.parentid, Some(753668));
+        assert_eq!(revision.pageid, 1007);
+        assert_eq!(revision.title, "Frequently asked questions");
+        assert!(revision.minor);
+        assert_eq!(
+            revision.sha1.as_deref(),
+            Some("8w7shy0k4hpj58vhrq1pdghk3e3row8")
+        );
+        assert_eq!(revision.model.as_deref(), Some("wikitext"));
+        assert_eq!(revision.format.as_deref(), Some("text/x-wiki"));
+        assert_eq!(revision.user, Contributor::User("Erus Iluvatar".into()));
+        assert_eq!(
+            revision.timestamp,
+            time::macros::datetime!(2023-01-23 08:34:52)
+        );
+    }
+
+    #[test]
+    fn test_anonymous_and_hidden_contributors() {
+        let dump = r#"<mediawiki>
+            <page>
+                <title>Foo</title>
+                <ns>0</ns>
+                <id>1</id>
+                <revision>
+                    <id>1</id>
+                    <timestamp>2023-01-01T00:00:00Z</timestamp>
+                    <contributor><ip>192.0.2.1</ip></contributor>
+                    <comment>anonymous</comment>
+                    <text>Hello</text>
+                </revision>
+                <revision>
+                    <id>2</id>
+                    <timestamp>2023-01-02T00:00:00Z</timestamp>
+                    <contributor deleted="deleted" />
+                    <comment>hidden</comment>
+                    <text>Hello world</text>
+                </revision>
+            </page>
+        </mediawiki>"#;
+        let mut reader = DumpReader::new(dump.as_bytes());
+        let page = reader.next_page().unwrap().unwrap();
+
+        let revision = reader.next_revision().unwrap().unwrap();
+        let revision = get_parsed_revision_from_dump(revision, &page);
+        assert_eq!(revision.user, Contributor::Ip("192.0.2.1".into()));
+
+        let revision = reader.next_revision().unwrap().unwrap();
+        let revision = get_parsed_revision_from_dump(revision, &page);
+        assert_eq!(revision.user, Contributor::Hidden);
+    }
+
+    #[test]
+    fn test_read_slots() {
+        let dump = r#"<mediawiki>
+            <page>
+                <title>File:Foo.png</title>
+                <ns>6</ns>
+                <id>1</id>
+                <revision>
+                    <id>1</id>
+                    <timestamp>2023-01-01T00:00:00Z</timestamp>
+                    <contributor><username>Foo</username></contributor>
+                    <model>wikitext</model>
+                    <format>text/x-wiki</format>
+                    <text bytes="5" xml:space="preserve">Hello</text>
+                    <sha1>abc</sha1>
+                    <content>
+                        <role>mediainfo</role>
+                        <origin rev="1" />
+                        <model>wikibase-mediainfo</model>
+                        <format>application/json</format>
+                        <text bytes="9" xml:space="preserve">{"id":"M1"}</text>
+                    </content>
+                </revision>
+            </page>
+        </mediawiki>"#;
+        let mut reader = DumpReader::new(dump.as_bytes());
+        let page = reader.next_page().unwrap().unwrap();
+        let revision = reader.next_revision().unwrap().unwrap();
+        let revision = get_parsed_revision_from_dump(revision, &page);
+
+        assert_eq!(revision.content, "Hello");
+        assert_eq!(
+            revision.slots,
+            [ParsedSlot {
+                role: "mediainfo".into(),
+                model: Some("wikibase-mediainfo".into()),
+                content: r#"{"id":"M1"}"#.into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_keep_text_whitespace() {
+        let dump = r#"<mediawiki>
+            <page>
+                <title>Foo</title>
+                <ns>0</ns>
+                <id>1</id>
+                <revision>
+                    <id>1</id>
+                    <timestamp>2023-01-01T00:00:00Z</timestamp>
+                    <contributor><username>Foo</username></contributor>
+                    <text bytes="22" xml:space="preserve">  indented &amp; code
+foo
+
+</text>
+                    <content>
+                        <role>extra</role>
+                        <text xml:space="preserve"> {"id": 1}
+</text>
+                    </content>
+                </revision>
+            </page>
+        </mediawiki>"#;
+        let mut reader = DumpReader::new(dump.as_bytes());
+        reader.next_page().unwrap().unwrap();
+        let revision = reader.next_revision().unwrap().unwrap();
+
+        assert_eq!(revision.text, "  indented & code\nfoo\n\n");
+        assert_eq!(revision.contents[0].text, " {\"id\": 1}\n");
+    }
+
+    #[test]
+    fn test_deleted_text() {
+        let dump = r#"<mediawiki>
+            <page>
+                <title>Foo</title>
+                <ns>0</ns>
+                <id>1</id>
+                <revision>
+                    <id>1</id>
+                    <timestamp>2023-01-01T00:00:00Z</timestamp>
+                    <contributor><username>Foo</username></contributor>
+                    <text>Hello</text>
+                </revision>
+                <revision>
+                    <id>2</id>
+                    <timestamp>2023-01-02T00:00:00Z</timestamp>
+                    <contributor><username>Foo</username></contributor>
+                    <text deleted="deleted" />
+                </revision>
+            </page>
+        </mediawiki>"#;
+        let mut reader = DumpReader::new(dump.as_bytes());
+        let page = reader.next_page().unwrap().unwrap();
+        let first = reader.next_revision().unwrap().unwrap();
+        let second = reader.next_revision().unwrap().unwrap();
+
+        assert!(!get_parsed_revision_from_dump(first, &page).text_hidden);
+        let second = get_parsed_revision_from_dump(second, &page);
+        assert!(second.text_hidden);
+        assert_eq!(second.content, "");
+    }
+
+    #[test]
+    fn test_skip_unread_revisions() {
+        let dump = r#"<mediawiki>
+            <siteinfo><sitename>Test</sitename></siteinfo>
+            <page>
+                <title>First</title>
+                <ns>0</ns>
+                <id>1</id>
+                <revision>
+                    <id>1</id>
+                    <timestamp>2023-01-01T00:00:00Z</timestamp>
+                    <contributor><username>Foo</username></contributor>
+                    <comment>first</comment>
+                    <text>Hello</text>
+                </revision>
+            </page>
+            <page>
+                <title>Second</title>
+                <ns>2</ns>
+                <id>2</id>
+                <redirect title="First" />
+            </page>
+        </mediawiki>"#;
+        let mut reader = DumpReader::new(dump.as_bytes());
+
+        let page = reader.next_page().unwrap().unwrap();
+        assert_eq!(page.title, "First");
+        let page = reader.next_page().unwrap().unwrap();
+        assert_eq!(page.title, "Second");
+        assert_eq!(page.ns, 2);
+        assert_eq!(page.redirect.unwrap().title, "First");
+        assert!(reader.next_revision().unwrap().is_none());
+        assert!(reader.next_page().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_uploads() {
+        let dump = r#"<mediawiki>
+            <page>
+                <title>File:Hello.txt</title>
+                <ns>6</ns>
+                <id>3</id>
+                <revision>
+                    <id>1</id>
+                    <timestamp>2023-01-01T00:00:00Z</timestamp>
+                    <contributor><username>Foo</username></contributor>
+                    <text>A greeting</text>
+                </revision>
+                <revision>
+                    <id>2</id>
+                    <timestamp>2023-01-02T00:00:00Z</timestamp>
+                    <contributor><username>Foo</username></contributor>
+                    <text>A friendly greeting</text>
+                </revision>
+                <upload>
+                    <timestamp>2023-01-01T00:00:00Z</timestamp>
+                    <contributor><ip>127.0.0.1</ip></contributor>
+                    <comment>Old version</comment>
+                    <filename>Hello.txt</filename>
+                    <src>https://example.com/images/a/ab/Hello.txt</src>
+                    <size>5</size>
+                </upload>
+                <upload>
+                    <timestamp>2023-01-03T00:00:00Z</timestamp>
+                    <contributor><username>Foo</username></contributor>
+                    <filename>Hello.txt</filename>
+                    <src>https://example.com/images/a/ab/Hello.txt</src>
+                    <size>5</size>
+                    <sha1base36>abc</sha1base36>
+                    <contents encoding="base64">SGVs
+bG8=
+</contents>
+                </upload>
+            </page>
+        </mediawiki>"#;
+        let mut reader = DumpReader::new(dump.as_bytes());
+
+        let page = reader.next_page().unwrap().unwrap();
+        assert_eq!(reader.next_revision().unwrap().unwrap().id, 1);
+        // The second revision is skipped
+        let first = get_parsed_upload_from_dump(reader.next_upload().unwrap().unwrap(), &page);
+        let second = get_parsed_upload_from_dump(reader.next_upload().unwrap().unwrap(), &page);
+        assert!(reader.next_upload().unwrap().is_none());
+        assert!(reader.next_page().unwrap().is_none());
+
+        assert_eq!(first.pageid, 3);
+        assert_eq!(first.title, "File:Hello.txt");
+        assert_eq!(first.user, Contributor::Ip("127.0.0.1".into()));
+        assert_eq!(first.comment, "Old version");
+        assert_eq!(
+            first.contents,
+            UploadContents::Url("https://example.com/images/a/ab/Hello.txt".into())
+        );
+        assert_eq!(
+            second.timestamp,
+            time::macros::datetime!(2023-01-03 00:00:00)
+        );
+        assert_eq!(second.sha1.as_deref(), Some("abc"));
+        assert_eq!(second.contents, UploadContents::Data(b"Hello".to_vec()));
+
+        // Uploads stop the revisions of the page
+        let mut reader = DumpReader::new(dump.as_bytes());
+        reader.next_page().unwrap();
+        assert!(reader.next_revision().unwrap().is_some());
+        assert!(reader.next_revision().unwrap().is_some());
+        assert!(reader.next_revision().unwrap().is_none());
+        assert!(reader.next_upload().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_invalid_upload_contents() {
+        let upload = UploadDump {
+            timestamp: "2023-01-01T00:00:00Z".into(),
+            filename: "Hello.txt".into(),
+            src: Some("https://example.com/images/a/ab/Hello.txt".into()),
+            contents: Some(UploadContentsDump {
+                encoding: "base64".into(),
+                data: "SGVs!".into(),
+            }),
+            ..Default::default()
+        };
+        let upload = get_parsed_upload_from_dump(upload, &PageDump::default());
+        assert_eq!(
+            upload.contents,
+            UploadContents::Url("https://example.com/images/a/ab/Hello.txt".into())
+        );
     }
 }
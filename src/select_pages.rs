@@ -0,0 +1,332 @@
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::path::Path;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::{info, trace, warn};
+
+use crate::api_client::{ApiClient, ApiError};
+use crate::fetch_all_pages::Page;
+use crate::fetch_category_members::{fetch_category_members, CATEGORY_NAMESPACE};
+use crate::fetch_pages_by_title::{fetch_pages_by_title, MAX_TITLES};
+use crate::fetch_recent_changes::fetch_recent_changes;
+use crate::fetch_site_info::MediaWikiVersion;
+use crate::parse_xml_dump::SiteInfo;
+
+/// Which pages of the selected namespaces to import
+#[derive(Clone, Debug, Default)]
+pub struct PageSelection {
+    /// Full titles of pages to import
+    pub titles: Vec<String>,
+    /// A category whose members to import, with or without its namespace prefix
+    pub category: Option<String>,
+    /// How many levels of subcategories to also import the members of
+    pub category_depth: u32,
+    /// Import the pages of the subcategories themselves, and not only their members
+    pub include_subcategories: bool,
+    /// A prefix of the titles to import, without their namespace prefix
+    pub prefix: Option<String>,
+    /// Only import the pages whose full title matches
+    pub include: Option<Regex>,
+    /// Don't import the pages whose full title matches
+    pub exclude: Option<Regex>,
+    /// Don't import the pages that are redirects
+    pub skip_redirects: bool,
+}
+
+impl PageSelection {
+    /// Whether the pages come from a list of titles or a category, rather than
+    /// from every page of a namespace
+    pub fn is_listed(&self) -> bool {
+        !self.titles.is_empty() || self.category.is_some()
+    }
+
+    /// Whether a page passes the `include` and `exclude` filters
+    pub fn matches(&self, title: &str) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|regex| regex.is_match(title))
+            && !self
+                .exclude
+                .as_ref()
+                .is_some_and(|regex| regex.is_match(title))
+    }
+
+    /// Keeps the changed pages that are part of the selection, i.e. of the
+    /// listed pages if any, with the prefix and passing the filters
+    pub fn filter_changed_pages(
+        &self,
+        changed_pages: BTreeMap<u32, Vec<Page>>,
+        selected_pages: Option<&BTreeMap<u32, Vec<Page>>>,
+    ) -> BTreeMap<u32, Vec<Page>> {
+        let selected_pageids = selected_pages.map(|selected_pages| {
+            selected_pages
+                .values()
+                .flatten()
+                .map(|page| page.pageid)
+                .collect::<HashSet<_>>()
+        });
+        changed_pages
+            .into_iter()
+            .map(|(namespace, mut pages)| {
+                pages.retain(|page| {
+                    let name = match namespace {
+                        0 => page.title.as_str(),
+                        _ => page.title.split_once(':').map_or("", |(_, name)| name),
+                    };
+                    self.matches(&page.title)
+                        && self
+                            .prefix
+                            .as_ref()
+                            .is_none_or(|prefix| name.starts_with(prefix.as_str()))
+                        && selected_pageids
+                            .as_ref()
+                            .is_none_or(|pageids| pageids.contains(&page.pageid))
+                });
+                (namespace, pages)
+            })
+            .filter(|(_, pages)| !pages.is_empty())
+            .collect()
+    }
+}
+
+/// Reads a file with one title per line. Empty lines and lines starting with
+/// '#', which can't be in titles, are skipped.
+pub fn load_titles(filename: &Path) -> Result<Vec<String>, std::io::Error> {
+    let titles = std::fs::read_to_string(filename)?;
+    Ok(titles
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Finds the pages of a listed selection that match its filters, by namespace.
+pub async fn fetch_selected_pages(
+    client: &ApiClient,
+    url: &str,
+    selection: &PageSelection,
+    site_info: &SiteInfo,
+    version: &MediaWikiVersion,
+) -> Result<BTreeMap<u32, Vec<Page>>, ApiError> {
+    let mut selected_pages: BTreeMap<u32, Vec<Page>> = BTreeMap::new();
+    let mut pageids = HashSet::new();
+    let mut add_page = |namespace: u32, page: Page| {
+        if selection.matches(&page.title) && pageids.insert(page.pageid) {
+            selected_pages.entry(namespace).or_default().push(page);
+        }
+    };
+
+    for titles in selection.titles.chunks(MAX_TITLES) {
+        let resp = fetch_pages_by_title(client, url, titles).await?;
+        let mut pages = resp
+            .query
+            .map(|query| query.pages.into_values().collect::<Vec<_>>())
+            .unwrap_or_default();
+        pages.sort_by(|a, b| a.title.cmp(&b.title));
+        for page in pages {
+            match (page.pageid, page.ns) {
+                (Some(_), Some(_)) if selection.skip_redirects && page.redirect.is_some() => {
+                    info!("Page '{}' is a redirect, skipping", page.title)
+                }
+                (Some(pageid), Some(namespace)) => {
+                    let title = page.title;
+                    add_page(namespace, Page { pageid, title });
+                }
+                _ if page.invalid.is_some() => warn!("Invalid title '{}', skipping", page.title),
+                _ => warn!("Page '{}' doesn't exist, skipping", page.title),
+            }
+        }
+    }
+
+    if let Some(category) = &selection.category {
+        let mut categories = VecDeque::from([(get_category_title(category, site_info), 0)]);
+        let mut visited_categories = HashSet::new();
+        while let Some((category, depth)) = categories.pop_front() {
+            if !visited_categories.insert(category.clone()) {
+                continue;
+            }
+            info!("Fetching the members of '{}'", category);
+
+            let mut continue_token = None;
+            loop {
+                let resp =
+                    fetch_category_members(client, url, &category, None, continue_token, version)
+                        .await?;
+                for member in resp.query.categorymembers {
+                    if member.ns == CATEGORY_NAMESPACE {
+                        if depth < selection.category_depth {
+                            categories.push_back((member.title.clone(), depth + 1));
+                        }
+                        if !selection.include_subcategories {
+                            trace!("Not importing subcategory '{}'", member.title);
+                            continue;
+                        }
+                    }
+                    let (pageid, title) = (member.pageid, member.title);
+                    add_page(member.ns, Page { pageid, title });
+                }
+
+                continue_token = resp.cont;
+                if continue_token.is_none() {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(selected_pages)
+}
+
+/// Finds the pages of the given namespaces that were edited, created or moved since
+/// `since`, by namespace and under their current title. Also returns the date of
+/// the most recent change, which the next sync can start from.
+pub async fn fetch_changed_pages(
+    client: &ApiClient,
+    url: &str,
+    since: OffsetDateTime,
+    namespaces: &[u32],
+    version: &MediaWikiVersion,
+) -> Result<(BTreeMap<u32, Vec<Page>>, Option<OffsetDateTime>), ApiError> {
+    // The namespace and title of each page, in the order they first changed
+    let mut changed_pages: Vec<(u32, Page)> = Vec::new();
+    let mut indices: HashMap<u64, usize> = HashMap::new();
+    let mut last_change = None;
+
+    let mut continue_token = None;
+    loop {
+        let resp = fetch_recent_changes(
+            client,
+            url,
+            Some(since),
+            namespaces,
+            None,
+            continue_token,
+            version,
+        )
+        .await?;
+
+        for change in resp.query.recentchanges {
+            last_change = Some(OffsetDateTime::parse(&change.timestamp, &Rfc3339).unwrap());
+            let (namespace, title) = match (change.kind.as_str(), change.logtype.as_deref()) {
+                ("edit" | "new", _) | ("log", Some("restore")) => (change.ns, change.title.clone()),
+                ("log", Some("move")) => match change.get_move_target() {
+                    Some((namespace, title)) => (namespace, title.to_string()),
+                    None => continue,
+                },
+                // Deletions are mirrored from the deletion log instead
+                ("log", Some("delete")) => {
+                    trace!("Page '{}' was deleted", change.title);
+                    continue;
+                }
+                _ => continue,
+            };
+            if change.pageid == 0 || !namespaces.contains(&namespace) {
+                continue;
+            }
+            trace!("Page '{}' changed at {}", title, change.timestamp);
+
+            let page = Page {
+                pageid: change.pageid,
+                title,
+            };
+            match indices.get(&change.pageid) {
+                Some(index) => changed_pages[*index] = (namespace, page),
+                None => {
+                    indices.insert(change.pageid, changed_pages.len());
+                    changed_pages.push((namespace, page));
+                }
+            }
+        }
+
+        continue_token = resp.cont;
+        if continue_token.is_none() {
+            break;
+        }
+    }
+
+    let mut pages_by_namespace: BTreeMap<u32, Vec<Page>> = BTreeMap::new();
+    for (namespace, page) in changed_pages {
+        pages_by_namespace.entry(namespace).or_default().push(page);
+    }
+    Ok((pages_by_namespace, last_change))
+}
+
+/// Adds the namespace prefix to the name of a category if it's missing. The
+/// canonical "Category:" prefix works on every wiki.
+fn get_category_title(category: &str, site_info: &SiteInfo) -> String {
+    let local_prefix = site_info.namespace_name(CATEGORY_NAMESPACE);
+    let name = match category.split_once(':') {
+        Some((prefix, name))
+            if prefix.eq_ignore_ascii_case("Category") || Some(prefix) == local_prefix =>
+        {
+            name
+        }
+        _ => category,
+    };
+    format!("Category:{}", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let selection = PageSelection {
+            include: Some(Regex::new("^Help:").unwrap()),
+            exclude: Some(Regex::new(r"\(.*\)$").unwrap()),
+            ..Default::default()
+        };
+        assert!(selection.matches("Help:Editing"));
+        assert!(!selection.matches("Help:Editing (Español)"));
+        assert!(!selection.matches("Main page"));
+        assert!(PageSelection::default().matches("Main page"));
+    }
+
+    #[test]
+    fn test_filter_changed_pages() {
+        let page = |pageid, title: &str| Page {
+            pageid,
+            title: title.into(),
+        };
+        let changed_pages = BTreeMap::from([
+            (0, vec![page(1, "Foo"), page(2, "Bar")]),
+            (4, vec![page(3, "Project:Foo")]),
+        ]);
+        let selection = PageSelection {
+            prefix: Some("F".into()),
+            ..Default::default()
+        };
+        let pages = selection.filter_changed_pages(changed_pages, None);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[&0][0].title, "Foo");
+        assert_eq!(pages[&4][0].title, "Project:Foo");
+
+        let selected_pages = BTreeMap::from([(4, vec![page(3, "Project:Foo")])]);
+        let pages = PageSelection::default().filter_changed_pages(pages, Some(&selected_pages));
+        assert_eq!(pages.keys().collect::<Vec<_>>(), [&4]);
+    }
+
+    #[test]
+    fn test_load_titles() {
+        let filename = std::env::temp_dir().join("test_load_titles.txt");
+        std::fs::write(&filename, "# Pages to import\nFoo\n\n  Help:Bar \n").unwrap();
+        assert_eq!(load_titles(&filename).unwrap(), ["Foo", "Help:Bar"]);
+        std::fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn test_get_category_title() {
+        let site_info = SiteInfo::default();
+        assert_eq!(get_category_title("Foo", &site_info), "Category:Foo");
+        assert_eq!(
+            get_category_title("category:Foo", &site_info),
+            "Category:Foo"
+        );
+        assert_eq!(
+            get_category_title("Foo: Bar", &site_info),
+            "Category:Foo: Bar"
+        );
+    }
+}